@@ -0,0 +1,86 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+//Archive of full responses that did not fit in a comment,
+//stored in "data_dir"/archive and served at /archive/{id}
+
+use std::error::Error;
+use std::fs;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+extern crate chrono;
+use self::chrono::*;
+
+use config;
+
+include!("logger_macros.rs");
+
+static ARCHIVE_COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+
+
+////////////////////////////////////////////////////////////
+//                          Funcs                         //
+////////////////////////////////////////////////////////////
+
+//Stores the text, returns the url it is served at
+pub fn store(tsconfig: &Arc<Mutex<config::ConfigHandler>>, content: &str) -> Result<String, String> {
+
+    thread_trace!("archive.rs: store(tsconfig, content)");
+
+    let id = format!("{}-{}", UTC::now().format("%Y%m%d%H%M%S"), ARCHIVE_COUNTER.fetch_add(1, Ordering::SeqCst));
+
+    let archive_dir: PathBuf;
+    let public_url:  String;
+    {
+        let mut config = tsconfig.lock().unwrap();
+        archive_dir    = config.get_data_file_path("archive");
+        public_url     = format!("{}:{}", config.get_string_required("config", "public_ip_address"), config.get_string_required("config", "listen_port"));
+    }
+
+    thread_trace!("  Create archive directory");
+    match fs::create_dir_all(&archive_dir) {
+        Ok(())   => (),
+        Err(err) => return Err(format!("Failed to create the archive directory \"{}\": {}", archive_dir.display(), err.description()))
+    }
+
+    let mut path = archive_dir.clone();
+    path.push(format!("{}.md", id));
+
+    thread_trace!("  Write {}", path.display());
+    match File::create(&path).and_then(|mut file| file.write_all(content.as_bytes())) {
+        Ok(())   => (),
+        Err(err) => return Err(format!("Failed to write \"{}\": {}", path.display(), err.description()))
+    }
+
+    thread_trace!("Return Ok");
+    Ok(format!("{}/archive/{}", public_url, id))
+}
+
+//Ok(None) if there is no such entry
+pub fn load(tsconfig: &Arc<Mutex<config::ConfigHandler>>, id: &str) -> Result<Option<String>, String> {
+
+    thread_trace!("archive.rs: load(tsconfig, \"{}\")", id);
+
+    //Ids are generated by store(), anything else could escape the archive directory
+    if id.is_empty() || !id.chars().all(|c| c.is_digit(10) || c == '-') {
+        thread_trace!("Return Ok(None)");
+        return Ok(None)
+    }
+
+    let mut path = tsconfig.lock().unwrap().get_data_file_path("archive");
+    path.push(format!("{}.md", id));
+    if !path.exists() {
+        thread_trace!("Return Ok(None)");
+        return Ok(None)
+    }
+
+    let mut content = String::new();
+    match File::open(&path).and_then(|mut file| file.read_to_string(&mut content)) {
+        Ok(_)    => Ok(Some(content)),
+        Err(err) => Err(format!("Failed to read \"{}\": {}", path.display(), err.description()))
+    }
+}
@@ -0,0 +1,166 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+use std::collections::BTreeMap;
+
+include!("logger_macros.rs");
+
+
+////////////////////////////////////////////////////////////
+//                      CommandArgs                       //
+////////////////////////////////////////////////////////////
+
+/// CommandArgs
+/// name:       Command name, i.e "test" in "test Boost --toolchain gcc"
+/// positional: Arguments that are not options, i.e ["Boost"]
+/// options:    "--name value" and "--name=value" options, a bare "--name"
+///             followed by another option or by nothing is stored with an
+///             empty value. A "--" token ends the options, what follows is
+///             positional.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommandArgs {
+    pub name:       String,
+    pub positional: Vec<String>,
+    pub options:    BTreeMap<String, String>
+}
+
+impl CommandArgs {
+
+    pub fn new(name: &str) -> CommandArgs {
+        CommandArgs {
+            name:       String::from(name),
+            positional: Vec::new(),
+            options:    BTreeMap::new()
+        }
+    }
+
+    //Parses the tokens of a command line, the first token is the command name
+    pub fn from_tokens(tokens: Vec<String>) -> Result<CommandArgs, String> {
+
+        thread_trace!("args.rs: CommandArgs::from_tokens({:?})", tokens);
+
+        let mut tokens = tokens.into_iter();
+        let mut args   = match tokens.next() {
+            Some(name) => CommandArgs::new(&name),
+            None       => return Err(String::from("The command is empty."))
+        };
+
+        let mut options_ended           = false;
+        let mut pending: Option<String> = None;
+
+        for token in tokens {
+            if !options_ended && token == "--" {
+                if let Some(name) = pending.take() {
+                    args.options.insert(name, String::new());
+                }
+                options_ended = true;
+                continue;
+            }
+
+            if !options_ended && token.starts_with("--") && token.len() > 2 {
+                if let Some(name) = pending.take() {
+                    args.options.insert(name, String::new());
+                }
+                let option = &token[2..];
+                match option.find('=') {
+                    Some(index) => {args.options.insert(String::from(&option[..index]), String::from(&option[index + 1..]));}
+                    None        => {pending = Some(String::from(option));}
+                }
+                continue;
+            }
+
+            match pending.take() {
+                Some(name) => {args.options.insert(name, token);}
+                None       => {args.positional.push(token);}
+            }
+        }
+        if let Some(name) = pending.take() {
+            args.options.insert(name, String::new());
+        }
+
+        Ok(args)
+    }
+
+    pub fn positional(&self, index: usize) -> Option<&str> {
+        self.positional.get(index).map(|arg| &arg[..])
+    }
+
+    pub fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(|value| &value[..])
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                          Funcs                         //
+////////////////////////////////////////////////////////////
+
+///Splits a command line into tokens the way a shell would:
+///  'single quotes' are taken literally,
+///  "double quotes" allow \" and \\ escapes,
+///  outside of quotes a backslash escapes the next character.
+pub fn tokenize(input: &str) -> Result<Vec<String>, String> {
+
+    thread_trace!("args.rs: tokenize(\"{}\")", input);
+
+    let mut tokens:   Vec<String> = Vec::new();
+    let mut current:  String      = String::new();
+    let mut in_token: bool        = false;
+    let mut chars                 = input.chars().enumerate();
+
+    while let Some((column, c)) = chars.next() {
+        match c {
+            '\'' => {
+                in_token = true;
+                loop {
+                    match chars.next() {
+                        Some((_, '\'')) => break,
+                        Some((_, c))    => current.push(c),
+                        None            => return Err(format!("The single quote at column {} is never closed.", column + 1))
+                    }
+                }
+            }
+            '"'  => {
+                in_token = true;
+                loop {
+                    match chars.next() {
+                        Some((_, '"'))  => break,
+                        Some((_, '\\')) => {
+                            match chars.next() {
+                                Some((_, c)) if c == '"' || c == '\\' => current.push(c),
+                                Some((_, c))                          => {current.push('\\'); current.push(c);}
+                                None                                  => return Err(format!("The double quote at column {} is never closed.", column + 1))
+                            }
+                        }
+                        Some((_, c))    => current.push(c),
+                        None            => return Err(format!("The double quote at column {} is never closed.", column + 1))
+                    }
+                }
+            }
+            '\\' => {
+                in_token = true;
+                match chars.next() {
+                    Some((_, c)) => current.push(c),
+                    None         => return Err(format!("The backslash at column {} does not escape anything.", column + 1))
+                }
+            }
+            c if c.is_whitespace() => {
+                if in_token {
+                    tokens.push(current.clone());
+                    current.clear();
+                    in_token = false;
+                }
+            }
+            c    => {
+                in_token = true;
+                current.push(c);
+            }
+        }
+    }
+    if in_token {
+        tokens.push(current);
+    }
+
+    thread_trace!("Return Ok({:?})", tokens);
+    Ok(tokens)
+}
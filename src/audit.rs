@@ -0,0 +1,224 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+//Every command the bot dispatches, logged in "data_dir"/audit.json, one JSON
+//object per line. Entries are only ever appended.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+extern crate chrono;
+use self::chrono::*;
+
+extern crate serde_json;
+
+use commands;
+use config;
+use permissions::Role;
+use response;
+use response::Message;
+use store;
+use webhooks;
+
+include!("logger_macros.rs");
+
+const AUDIT_FILE_NAME: &'static str = "audit.json";
+
+//Entries shown by the audit command
+const MAX_ROWS: usize = 20;
+
+
+////////////////////////////////////////////////////////////
+//                       AuditEntry                       //
+////////////////////////////////////////////////////////////
+
+/// AuditEntry
+/// at:          Unix timestamp of when the command was run
+/// delivery:    Github ID of the webhook delivery, empty if unknown
+/// user:        User who gave the command
+/// role:        Role of the user at the time, i.e "Write" or "Anyone+Whitelisted",
+///              "Unknown" if the command was rejected before its permission was checked
+/// number:      Issue or PR number
+/// command:     Command line, with its arguments
+/// status:      Outcome, i.e "Succeeded", "Rejected" or "Started" for jobs
+/// duration_ms: Time it took to run the command, jobs only count their start
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    pub at:          i64,
+    pub delivery:    String,
+    pub user:        String,
+    pub role:        String,
+    pub number:      u64,
+    pub command:     String,
+    pub status:      String,
+    pub duration_ms: u64
+}
+
+impl AuditEntry {
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut object: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+        object.insert(String::from("at"),          serde_json::Value::I64(self.at));
+        object.insert(String::from("delivery"),    serde_json::Value::String(self.delivery.clone()));
+        object.insert(String::from("user"),        serde_json::Value::String(self.user.clone()));
+        object.insert(String::from("role"),        serde_json::Value::String(self.role.clone()));
+        object.insert(String::from("number"),      serde_json::Value::U64(self.number));
+        object.insert(String::from("command"),     serde_json::Value::String(self.command.clone()));
+        object.insert(String::from("status"),      serde_json::Value::String(self.status.clone()));
+        object.insert(String::from("duration_ms"), serde_json::Value::U64(self.duration_ms));
+        serde_json::Value::Object(object)
+    }
+
+    pub fn from_json(value: &serde_json::Value) -> Result<AuditEntry, String> {
+        let object = try!(value.as_object().ok_or(String::from("An audit entry does not describe an object.")));
+        Ok(AuditEntry {
+            at:          try!(value.find("at").and_then(|at| at.as_i64()).ok_or(String::from("The \"at\" field does not describe a number."))),
+            delivery:    try!(webhooks::extract_json_string(object, "delivery")),
+            user:        try!(webhooks::extract_json_string(object, "user")),
+            role:        try!(webhooks::extract_json_string(object, "role")),
+            number:      try!(webhooks::extract_json_u64(object, "number")),
+            command:     try!(webhooks::extract_json_string(object, "command")),
+            status:      try!(webhooks::extract_json_string(object, "status")),
+            duration_ms: try!(webhooks::extract_json_u64(object, "duration_ms"))
+        })
+    }
+
+    pub fn csv_header() -> String {
+        String::from("at,delivery,user,role,number,command,status,duration_ms")
+    }
+
+    pub fn to_csv(&self) -> String {
+        vec![
+            format_time(self.at),
+            csv_field(&self.delivery),
+            csv_field(&self.user),
+            csv_field(&self.role),
+            self.number.to_string(),
+            csv_field(&self.command),
+            csv_field(&self.status),
+            self.duration_ms.to_string()
+        ].join(",")
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                      AuditCommand                      //
+////////////////////////////////////////////////////////////
+
+///"audit [user] [--since <date|age>]"
+pub struct AuditCommand;
+
+impl AuditCommand {
+    pub fn command() -> commands::Command {
+        commands::Command::new(false, Role::Owner, AuditCommand)
+            .summary("Lists the last commands given to the bot, who gave them and how they went.")
+            .usage("audit [user] [--since <date|age>]")
+            .argument("user", false, "Only list the commands of this Github user.")
+            .argument("--since", false, "Only list the commands given after a date (2016-11-30) or within an age (30m, 12h, 7d).")
+            .example("audit")
+            .example("audit octocat --since 7d")
+    }
+}
+
+impl commands::BotCommand for AuditCommand {
+    fn run(&self, context: &mut commands::CommandContext) -> Result<Message, String> {
+
+        thread_trace!("audit.rs: AuditCommand::run(&self, context)");
+
+        let since = match context.args.option("since") {
+            Some(since) => Some(try!(parse_since(since, UTC::now().timestamp()))),
+            None        => None
+        };
+        let entries = try!(entries(&context.tsconfig, context.args.positional(0), since));
+        if entries.is_empty() {
+            return Ok(Message::text("No command was recorded."))
+        }
+
+        let rows: Vec<Vec<String>> = entries.iter().rev().take(MAX_ROWS).map(|entry| vec![
+            format_time(entry.at),
+            entry.user.clone(),
+            entry.role.clone(),
+            format!("#{}", entry.number),
+            response::inline_code(&entry.command),
+            entry.status.clone(),
+            format!("{} ms", entry.duration_ms)
+        ]).collect();
+
+        Ok(Message::text(&format!("{} command(s), showing the last {}:", entries.len(), rows.len()))
+            .table(vec!["At", "User", "Role", "Issue", "Command", "Status", "Duration"], rows))
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                          Funcs                         //
+////////////////////////////////////////////////////////////
+
+pub fn record(tsconfig: &Arc<Mutex<config::ConfigHandler>>, entry: &AuditEntry) -> Result<(), String> {
+    thread_trace!("audit.rs: record(tsconfig, entry)");
+    store::Store::new(tsconfig).append(AUDIT_FILE_NAME, &entry.to_json())
+        .map_err(|err| format!("Failed to record the command in the audit log: {}", err))
+}
+
+//Recorded commands, oldest first.
+//user:  Only the commands of this user, the case is ignored
+//since: Only the commands run at this Unix timestamp or later
+pub fn entries(tsconfig: &Arc<Mutex<config::ConfigHandler>>, user: Option<&str>, since: Option<i64>) -> Result<Vec<AuditEntry>, String> {
+
+    thread_trace!("audit.rs: entries(tsconfig, {:?}, {:?})", user, since);
+
+    let mut entries: Vec<AuditEntry> = Vec::new();
+    for value in try!(store::Store::new(tsconfig).entries(AUDIT_FILE_NAME)) {
+        let entry = try!(AuditEntry::from_json(&value));
+        if user.map(|user| user.to_lowercase() != entry.user.to_lowercase()).unwrap_or(false) {
+            continue;
+        }
+        if since.map(|since| entry.at < since).unwrap_or(false) {
+            continue;
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+//"2016-11-30" is a date (UTC), "30m", "12h" and "7d" are ages counted back from now.
+//Ok: Unix timestamp
+pub fn parse_since(since: &str, now: i64) -> Result<i64, String> {
+
+    if let Ok(date) = NaiveDate::parse_from_str(since, "%Y-%m-%d") {
+        return Ok(date.and_hms(0, 0, 0).timestamp())
+    }
+
+    let invalid = format!("{} is neither a date (2016-11-30) nor an age (30m, 12h, 7d).", response::inline_code(since));
+    let unit    = match since.chars().last() {
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        _         => return Err(invalid)
+    };
+    match since[..since.len() - 1].parse::<i64>() {
+        Ok(count) => Ok(now - count * unit),
+        Err(_)    => Err(invalid)
+    }
+}
+
+pub fn to_csv(entries: &Vec<AuditEntry>) -> String {
+    let mut lines = vec![AuditEntry::csv_header()];
+    lines.extend(entries.iter().map(|entry| entry.to_csv()));
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+fn format_time(at: i64) -> String {
+    UTC.timestamp(at, 0).format("%Y-%m-%d %H:%M:%S UTC").to_string()
+}
+
+//Quoted if it contains a separator, a quote or a line break
+fn csv_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') || field.contains('\r') {
+        format!("\"{}\"", field.replace("\"", "\"\""))
+    } else {
+        String::from(field)
+    }
+}
@@ -0,0 +1,316 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+//"automerge": marks a pull request to be merged as soon as it is approved,
+//green and mergeable. Reviews, pushes and CI results of the marked PRs are
+//followed through their webhooks; the bot merges, or gives up and says why.
+//The marks are kept in "data_dir"/automerge.json, a dry run leaves them as
+//they are.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+extern crate chrono;
+use self::chrono::*;
+
+extern crate serde_json;
+
+use commands;
+use config;
+use github;
+use merge;
+use outbound;
+use permissions;
+use permissions::Role;
+use response;
+use response::Message;
+use store;
+use webhooks;
+
+include!("logger_macros.rs");
+
+const AUTOMERGE_FILE_NAME: &'static str = "automerge.json";
+
+
+////////////////////////////////////////////////////////////
+//                          Mark                          //
+////////////////////////////////////////////////////////////
+
+/// Mark
+/// number:    PR to merge
+/// method:    "merge", "squash" or "rebase"
+/// marked_by: User who gave the automerge command, told when it is done
+/// marked_at: Unix timestamp
+/// head_sha:  Head of the PR last time it was looked at, CI results of other commits are ignored
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mark {
+    pub number:    u64,
+    pub method:    String,
+    pub marked_by: String,
+    pub marked_at: i64,
+    pub head_sha:  String
+}
+
+impl Mark {
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut object: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+        object.insert(String::from("number"),    serde_json::Value::U64(self.number));
+        object.insert(String::from("method"),    serde_json::Value::String(self.method.clone()));
+        object.insert(String::from("marked_by"), serde_json::Value::String(self.marked_by.clone()));
+        object.insert(String::from("marked_at"), serde_json::Value::I64(self.marked_at));
+        object.insert(String::from("head_sha"),  serde_json::Value::String(self.head_sha.clone()));
+        serde_json::Value::Object(object)
+    }
+
+    pub fn from_json(value: &serde_json::Value) -> Result<Mark, String> {
+        let object = try!(value.as_object().ok_or(String::from("An automerge mark does not describe an object.")));
+        Ok(Mark {
+            number:    try!(webhooks::extract_json_u64(object, "number")),
+            method:    try!(webhooks::extract_json_string(object, "method")),
+            marked_by: try!(webhooks::extract_json_string(object, "marked_by")),
+            marked_at: value.find("marked_at").and_then(|marked_at| marked_at.as_i64()).unwrap_or(0),
+            head_sha:  webhooks::extract_json_string(object, "head_sha").unwrap_or(String::new())
+        })
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                    AutomergeCommand                    //
+////////////////////////////////////////////////////////////
+
+///"automerge [merge|squash|rebase|off]"
+pub struct AutomergeCommand;
+
+impl AutomergeCommand {
+    pub fn command() -> commands::Command {
+        commands::Command::new(false, Role::Maintain, AutomergeCommand)
+            .summary("Merges the pull request as soon as it is approved, green and mergeable.")
+            .usage("automerge [merge|squash|rebase|off]")
+            .argument("merge|squash|rebase|off", false, "How to merge, as for \"merge\". \"off\" unmarks the pull request.")
+            .example("automerge")
+            .example("automerge squash")
+            .example("automerge off")
+    }
+}
+
+impl commands::BotCommand for AutomergeCommand {
+    fn run(&self, context: &mut commands::CommandContext) -> Result<Message, String> {
+
+        thread_trace!("automerge.rs: AutomergeCommand::run(&self, context)");
+
+        let number = context.event.number;
+        if context.args.positional(0) == Some("off") {
+            return match try!(unmark(&context.tsconfig, number)) {
+                Some(_) => Ok(Message::text(&format!("#{} will not be merged automatically anymore.", number))),
+                None    => Ok(Message::text(&format!("#{} was not going to be merged automatically.", number)))
+            }
+        }
+
+        let method = match context.args.positional(0) {
+            Some(method) => String::from(method),
+            None         => context.config.get_string_or("config", "merge_method", "merge")
+        };
+        if !merge::MERGE_METHODS.iter().any(|known| *known == &method[..]) {
+            return Err(format!("{} is not a merge method, use one of {} or {}.", response::inline_code(&method),
+                merge::MERGE_METHODS.iter().map(|method| response::inline_code(method)).collect::<Vec<String>>().join(", "), response::inline_code("off")))
+        }
+
+        let repo      = context.config.get_string_required("config", "github_follow_repo");
+        let readiness = try!(merge::readiness(&context.github, &mut context.config, &repo, number));
+        if !readiness.failures().is_empty() {
+            return Ok(Message::text(&format!("#{} cannot be merged automatically:", number))
+                .list(&readiness.checklist()))
+        }
+        if readiness.is_ready() {
+            let sha = try!(merge::merge(&context.github, &repo, &readiness, &method, &context.event.user));
+            return Ok(Message::text(&format!("#{} is ready already, merged it into {} with {} ({}).", number,
+                response::inline_code(&readiness.pull.base_ref), response::inline_code(&method), sha)))
+        }
+
+        try!(save_mark(&context.tsconfig, Mark {
+            number:    number,
+            method:    method.clone(),
+            marked_by: context.event.user.clone(),
+            marked_at: UTC::now().timestamp(),
+            head_sha:  readiness.pull.head_sha.clone()
+        }));
+        Ok(Message::text(&format!("I will merge #{} with {} once it is ready:", number, response::inline_code(&method)))
+            .list(&readiness.checklist())
+            .paragraph(&format!("Say {} to change your mind.", response::inline_code("automerge off"))))
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                          Funcs                         //
+////////////////////////////////////////////////////////////
+
+//Follows the marked PRs: reviews and pushes of a PR, statuses and check
+//suites of its head commit. A push from someone who is not a maintainer
+//removes the mark, they could slip anything in after the approvals.
+pub fn on_event(tsconfig: &Arc<Mutex<config::ConfigHandler>>, outbound: &outbound::OutboundQueue, permissions: &permissions::PermissionCache, event: &webhooks::WebhookEvent) {
+
+    thread_trace!("automerge.rs: on_event(tsconfig, outbound, permissions, event)");
+
+    let marks = match marks(tsconfig) {
+        Ok(marks) => marks,
+        Err(err)  => {
+            thread_error!("{}", err);
+            return;
+        }
+    };
+    if marks.is_empty() {
+        return;
+    }
+
+    let client = github::GithubClient::bot(tsconfig);
+    match event.event_type {
+        webhooks::WebhookEventType::PullRequest       => {
+            let mark = match marks.into_iter().find(|mark| mark.number == event.number) {
+                Some(mark) => mark,
+                None       => return
+            };
+            if event.action == "closed" {
+                give_up(tsconfig, outbound, &mark, Message::text(&format!("Gave up merging #{} automatically, it was closed.", mark.number)));
+            } else if event.action == "synchronize" && !permissions.has_role(event, Role::Maintain) {
+                give_up(tsconfig, outbound, &mark, Message::text(&format!("Gave up merging #{} automatically, @{} pushed new commits and is not a maintainer.", mark.number, event.user))
+                    .paragraph(&format!("Say {} again once the changes are reviewed.", response::inline_code("automerge"))));
+            } else if event.action == "synchronize" && !event.sha.is_empty() {
+                let mark = Mark {head_sha: event.sha.clone(), .. mark};
+                if let Err(err) = save_mark(tsconfig, mark.clone()) {
+                    thread_error!("{}", err);
+                }
+                evaluate(tsconfig, outbound, &client, &mark, None);
+            } else {
+                evaluate(tsconfig, outbound, &client, &mark, None);
+            }
+        }
+        webhooks::WebhookEventType::PullRequestReview => {
+            if let Some(mark) = marks.into_iter().find(|mark| mark.number == event.number) {
+                evaluate(tsconfig, outbound, &client, &mark, None);
+            }
+        }
+        //Only a finished CI run of the head of a marked PR can make it ready,
+        //marks saved without a head are looked up
+        webhooks::WebhookEventType::Status            |
+        webhooks::WebhookEventType::CheckSuite        => {
+            if event.action == "pending" || event.action == "requested" || event.action == "rerequested" {
+                return;
+            }
+            for mark in marks.iter().filter(|mark| mark.head_sha.is_empty() || mark.head_sha == event.sha) {
+                evaluate(tsconfig, outbound, &client, mark, Some(&event.sha));
+            }
+        }
+        _                                             => ()
+    }
+}
+
+//Merges the PR if it is ready, gives up if it needs someone to act
+//sha: Only if this commit is the head of the PR
+fn evaluate(tsconfig: &Arc<Mutex<config::ConfigHandler>>, outbound: &outbound::OutboundQueue, client: &github::GithubClient, mark: &Mark, sha: Option<&str>) {
+
+    thread_trace!("automerge.rs: evaluate(tsconfig, outbound, client, {:?}, {:?})", mark, sha);
+
+    //The checks make several calls, they run on a copy of the config
+    let mut config = tsconfig.lock().unwrap().clone();
+    let     repo   = config.get_string_required("config", "github_follow_repo");
+    let readiness  = match merge::readiness(client, &mut config, &repo, mark.number) {
+        Ok(readiness) => readiness,
+        Err(err)      => {
+            thread_error!("Failed to check if #{} can be merged: {}", mark.number, err);
+            return;
+        }
+    };
+
+    //Pushes the webhooks did not tell about
+    if readiness.pull.head_sha != mark.head_sha {
+        if let Err(err) = save_mark(tsconfig, Mark {head_sha: readiness.pull.head_sha.clone(), .. mark.clone()}) {
+            thread_error!("{}", err);
+        }
+    }
+    if sha.map(|sha| sha != readiness.pull.head_sha).unwrap_or(false) {
+        return;
+    }
+    if !readiness.failures().is_empty() {
+        give_up(tsconfig, outbound, mark, Message::text(&format!("Gave up merging #{} automatically:", mark.number)).list(&readiness.checklist()));
+        return;
+    }
+    if !readiness.is_ready() {
+        thread_debug!("#{} is not ready yet", mark.number);
+        return;
+    }
+
+    match merge::merge(client, &repo, &readiness, &mark.method, &mark.marked_by) {
+        Ok(merge_sha) => {
+            thread_info!("Merged #{} automatically", mark.number);
+            if let Err(err) = unmark(tsconfig, mark.number) {
+                thread_error!("{}", err);
+            }
+            report(tsconfig, outbound, mark, Message::text(&format!("Merged #{} automatically into {} with {} ({}).", mark.number,
+                response::inline_code(&readiness.pull.base_ref), response::inline_code(&mark.method), merge_sha))
+                .list(&readiness.checklist()));
+        }
+        Err(err)      => give_up(tsconfig, outbound, mark, Message::text(&format!("Gave up merging #{} automatically: {}", mark.number, err)))
+    }
+}
+
+fn give_up(tsconfig: &Arc<Mutex<config::ConfigHandler>>, outbound: &outbound::OutboundQueue, mark: &Mark, msg: Message) {
+    thread_info!("Gave up merging #{} automatically", mark.number);
+    if let Err(err) = unmark(tsconfig, mark.number) {
+        thread_error!("{}", err);
+    }
+    report(tsconfig, outbound, mark, msg);
+}
+
+//Tells the user who marked the PR
+fn report(tsconfig: &Arc<Mutex<config::ConfigHandler>>, outbound: &outbound::OutboundQueue, mark: &Mark, msg: Message) {
+    let mut event = webhooks::WebhookEvent::new();
+    event.number  = mark.number;
+    event.user    = mark.marked_by.clone();
+    commands::respond(tsconfig, outbound, event, msg);
+}
+
+//Marked PRs, in the order they were marked
+pub fn marks(tsconfig: &Arc<Mutex<config::ConfigHandler>>) -> Result<Vec<Mark>, String> {
+    let value = match try!(store::Store::new(tsconfig).load(AUTOMERGE_FILE_NAME)) {
+        Some(value) => value,
+        None        => return Ok(Vec::new())
+    };
+    let mut marks: Vec<Mark> = Vec::new();
+    for mark in value.find("pulls").and_then(|pulls| pulls.as_array()).cloned().unwrap_or(Vec::new()) {
+        marks.push(try!(Mark::from_json(&mark)));
+    }
+    Ok(marks)
+}
+
+//Replaces the mark of the same PR, if any
+fn save_mark(tsconfig: &Arc<Mutex<config::ConfigHandler>>, mark: Mark) -> Result<(), String> {
+    let mut marks = try!(marks(tsconfig));
+    marks.retain(|other| other.number != mark.number);
+    marks.push(mark);
+    save_marks(tsconfig, &marks)
+}
+
+//Ok: The removed mark, None if the PR was not marked
+pub fn unmark(tsconfig: &Arc<Mutex<config::ConfigHandler>>, number: u64) -> Result<Option<Mark>, String> {
+    let mut marks   = try!(marks(tsconfig));
+    let     removed = marks.iter().find(|mark| mark.number == number).cloned();
+    if removed.is_some() {
+        marks.retain(|mark| mark.number != number);
+        try!(save_marks(tsconfig, &marks));
+    }
+    Ok(removed)
+}
+
+fn save_marks(tsconfig: &Arc<Mutex<config::ConfigHandler>>, marks: &Vec<Mark>) -> Result<(), String> {
+    if tsconfig.lock().unwrap().is_dry_run() {
+        thread_info!("Dry run, the automerge marks are not saved");
+        return Ok(())
+    }
+    let mut object: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+    object.insert(String::from("pulls"), serde_json::Value::Array(marks.iter().map(|mark| mark.to_json()).collect()));
+    store::Store::new(tsconfig).save(AUTOMERGE_FILE_NAME, &serde_json::Value::Object(object))
+        .map_err(|err| format!("Failed to save the automerge marks: {}", err))
+}
@@ -2,12 +2,37 @@
 //All rights reserved.
 
 
+use std::cmp;
 use std::collections::BTreeMap;
 use std::fmt;
 use std::sync::{Arc, Mutex};
 
+extern crate chrono;
+use self::chrono::*;
+
+use archive;
+use args;
+use audit;
+use automerge;
 use config;
+use github;
+use help;
+use jobs;
+use merge;
+use moderation;
+use outbound;
+use permissions;
+use permissions::Role;
+use queue;
+use reactions;
+use response;
+use response::Message;
+use status;
+use sticky;
+use store;
+use templates;
 use webhooks;
+use whitelist;
 
 include!("logger_macros.rs");
 
@@ -16,28 +41,265 @@ include!("logger_macros.rs");
 //                         Command                        //
 ////////////////////////////////////////////////////////////
 
-//                            Config,                             Raw webhook,            Arguments
-pub type CommandCallback = fn(&Arc<Mutex<config::ConfigHandler>>, webhooks::WebhookEvent, Vec<&str>) -> Result<String, String>;
+///A command of the bot. Ok is the reply, Err is reported to the user as a failure.
+///Plain functions taking a CommandContext are commands too:
+///
+///    fn ping(context: &mut CommandContext) -> Result<Message, String>
+pub trait BotCommand: Send + Sync {
+    fn run(&self, context: &mut CommandContext) -> Result<Message, String>;
+}
+
+impl<F> BotCommand for F where F: Fn(&mut CommandContext) -> Result<Message, String> + Send + Sync {
+    fn run(&self, context: &mut CommandContext) -> Result<Message, String> {
+        self(context)
+    }
+}
 
+/// Command
+/// requires_please: The command is only run if the user says please
+/// role:            Minimum role of the users who can run the command
+/// whitelisted:     Whitelisted users can run the command whatever their role
+/// aliases:         Other names the command can be called by
+/// summary:         One line description, shown in the help table
+/// usage:           Synopsis, i.e "deadletters [count]"
+/// arguments:       Description of every argument and option
+/// examples:        Command lines showing how the command is used
+/// timeout:         Set for commands that run as background jobs, in seconds
 pub struct Command {
     pub requires_please: bool,
-    pub whitelist_only:  bool,
-    pub callback:        CommandCallback
+    pub role:            permissions::Role,
+    pub whitelisted:     bool,
+    pub aliases:         Vec<String>,
+    pub summary:         String,
+    pub usage:           String,
+    pub arguments:       Vec<CommandArgument>,
+    pub examples:        Vec<String>,
+    pub timeout:         Option<u64>,
+    pub handler:         Arc<BotCommand>
 }
 
 impl Command {
-    pub fn new(requires_please: bool, whitelist_only: bool, callback: CommandCallback) -> Command {
+    pub fn new<H: BotCommand + 'static>(requires_please: bool, role: permissions::Role, handler: H) -> Command {
         Command{
             requires_please: requires_please,
-            whitelist_only:  whitelist_only,
-            callback:        callback
+            role:            role,
+            whitelisted:     false,
+            aliases:         Vec::new(),
+            summary:         String::new(),
+            usage:           String::new(),
+            arguments:       Vec::new(),
+            examples:        Vec::new(),
+            timeout:         None,
+            handler:         Arc::new(handler)
+        }
+    }
+
+    pub fn aliases(mut self, aliases: Vec<&str>) -> Command {
+        self.aliases = aliases.iter().map(|alias| String::from(*alias)).collect();
+        self
+    }
+
+    pub fn summary(mut self, summary: &str) -> Command {
+        self.summary = String::from(summary);
+        self
+    }
+
+    pub fn usage(mut self, usage: &str) -> Command {
+        self.usage = String::from(usage);
+        self
+    }
+
+    //name: "count" for a positional argument, "--toolchain" for an option
+    pub fn argument(mut self, name: &str, required: bool, description: &str) -> Command {
+        self.arguments.push(CommandArgument {
+            name:        String::from(name),
+            required:    required,
+            description: String::from(description)
+        });
+        self
+    }
+
+    pub fn example(mut self, example: &str) -> Command {
+        self.examples.push(String::from(example));
+        self
+    }
+
+    //Runs the command as a background job, stopped after `timeout` seconds
+    pub fn background(mut self, timeout: u64) -> Command {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    //Lets the whitelisted users run the command too
+    pub fn whitelisted(mut self) -> Command {
+        self.whitelisted = true;
+        self
+    }
+
+    //Can a user with this role run the command?
+    pub fn allows(&self, user_role: permissions::Role, whitelisted: bool) -> bool {
+        self.role <= user_role || (self.whitelisted && whitelisted)
+    }
+
+    //Who can run the command, as shown in the help
+    pub fn permission(&self) -> String {
+        match self.whitelisted {
+            true  => format!("{} and whitelisted users", self.role.description()),
+            false => String::from(self.role.description())
+        }
+    }
+
+    //Completes "You need to be ... to run that command"
+    pub fn requirement(&self) -> String {
+        match self.whitelisted {
+            true  => format!("{} or whitelisted", self.role.requirement()),
+            false => String::from(self.role.requirement())
         }
     }
 }
 
 impl fmt::Debug for Command {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Command {{requires_please: {}, whitelist_only: {}, callback: fn(&Arc<Mutex<config::ConfigHandler>>, webhooks::WebhookEvent, Vec<&str>) -> Result<String, String>}}", self.requires_please, self.whitelist_only)
+        write!(f, "Command {{requires_please: {}, role: {:?}, whitelisted: {}, aliases: {:?}, summary: {:?}, usage: {:?}, arguments: {:?}, examples: {:?}, timeout: {:?}}}",
+            self.requires_please, self.role, self.whitelisted, self.aliases, self.summary, self.usage, self.arguments, self.examples, self.timeout)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommandArgument {
+    pub name:        String,
+    pub required:    bool,
+    pub description: String
+}
+
+
+////////////////////////////////////////////////////////////
+//                     CommandRegistry                    //
+////////////////////////////////////////////////////////////
+
+/// CommandRegistry
+/// commands: Registered commands by name
+/// aliases:  Command name by alias
+#[derive(Debug)]
+pub struct CommandRegistry {
+    commands: BTreeMap<String, Command>,
+    aliases:  BTreeMap<String, String>
+}
+
+impl CommandRegistry {
+
+    pub fn new() -> CommandRegistry {
+        CommandRegistry {
+            commands: BTreeMap::new(),
+            aliases:  BTreeMap::new()
+        }
+    }
+
+    //Commands that come with the bot, also used to render the help and the docs
+    pub fn builtin() -> CommandRegistry {
+
+        let mut registry = CommandRegistry::new();
+
+        registry.register("ping", Command::new(false, Role::Anyone, ping)
+            .summary("Checks that the bot is alive.")
+            .usage("ping")
+            .example("ping"));
+        registry.register("help", Command::new(false, Role::Anyone, help)
+            .aliases(vec!["commands"])
+            .summary("Lists the commands you can use, or shows how to use one of them.")
+            .usage("help [command]")
+            .argument("command", false, "Name or alias of the command to describe.")
+            .example("help")
+            .example("help deadletters"));
+        registry.register("deadletters", Command::new(false, Role::Owner, deadletters)
+            .whitelisted()
+            .aliases(vec!["dead-letters", "undelivered"])
+            .summary("Lists the replies the bot gave up on delivering.")
+            .usage("deadletters [count]")
+            .argument("count", false, "Number of messages to show, the most recent first. 10 by default.")
+            .example("deadletters 20"));
+        registry.register("whitelist", whitelist::WhitelistCommand::command());
+        registry.register("cancel",    jobs::CancelCommand::command());
+        registry.register("jobs",      jobs::JobsCommand::command());
+        registry.register("audit",     audit::AuditCommand::command());
+        registry.register("status",    status::StatusCommand::command());
+        registry.register("label",     moderation::LabelCommand::command());
+        registry.register("close",     moderation::CloseCommand::command());
+        registry.register("reopen",    moderation::ReopenCommand::command());
+        registry.register("lock",      moderation::LockCommand::command());
+        registry.register("unlock",    moderation::UnlockCommand::command());
+        registry.register("assign",    moderation::AssignCommand::command());
+        registry.register("unassign",  moderation::UnassignCommand::command());
+        registry.register("merge",     merge::MergeCommand::command());
+        registry.register("automerge", automerge::AutomergeCommand::command());
+        registry.register("r+",        queue::ApproveCommand::command());
+        registry.register("r-",        queue::UnapproveCommand::command());
+        registry.register("queue",     queue::QueueCommand::command());
+
+        registry
+    }
+
+    pub fn register(&mut self, name: &str, command: Command) {
+        thread_trace!("commands.rs: CommandRegistry::register(&mut self, \"{}\", command)", name);
+        for alias in command.aliases.iter() {
+            if self.commands.contains_key(alias) || self.aliases.contains_key(alias) {
+                thread_crash!("The alias \"{}\" of the command \"{}\" is already taken.", alias, name);
+            }
+            self.aliases.insert(alias.clone(), String::from(name));
+        }
+        self.commands.insert(String::from(name), command);
+    }
+
+    //Finds a command by name or alias, case insensitive. Returns the command name along with it.
+    pub fn find(&self, name: &str) -> Option<(String, &Command)> {
+        let name = name.to_lowercase();
+        let name = match self.aliases.get(&name) {
+            Some(command_name) => command_name.clone(),
+            None               => name
+        };
+        self.commands.get(&name).map(|command| (name.clone(), command))
+    }
+
+    //Registered commands sorted by name
+    pub fn commands(&self) -> &BTreeMap<String, Command> {
+        &self.commands
+    }
+
+    //Reply for an unknown command: the closest names and the commands the user can run
+    pub fn command_not_found(&self, name: &str, user_role: Role, whitelisted: bool, templates: &templates::Templates) -> Message {
+
+        thread_trace!("commands.rs: CommandRegistry::command_not_found(&self, \"{}\", {:?}, {})", name, user_role, whitelisted);
+
+        let name      = name.to_lowercase();
+        let threshold = cmp::max(2, cmp::min(3, name.chars().count() / 3));
+
+        let mut candidates: Vec<(usize, &String)> = self.commands.keys()
+            .chain(self.aliases.keys())
+            .map(|candidate| (edit_distance(&name, candidate), candidate))
+            .filter(|&(distance, _)| distance <= threshold)
+            .collect();
+        candidates.sort();
+
+        let mut suggestions: Vec<String> = Vec::new();
+        if let Some(&(best, _)) = candidates.first() {
+            for &(distance, candidate) in candidates.iter() {
+                let suggestion = response::inline_code(candidate);
+                if distance == best && !suggestions.contains(&suggestion) {
+                    suggestions.push(suggestion);
+                }
+            }
+        }
+
+        let available: Vec<String> = self.commands.iter()
+            .filter(|&(_, command)| command.allows(user_role, whitelisted))
+            .map(|(command_name, _)| response::inline_code(command_name))
+            .collect();
+
+        let mut message = Message::text(&templates.render("not_found", &[("command", &name[..])]));
+        if !suggestions.is_empty() {
+            message = message.paragraph(&templates.render("suggestions", &[("command", &name[..]), ("suggestions", &suggestions.join(" or ")[..])]));
+        }
+        message.paragraph(&templates.render("available_commands", &[("command", &name[..]), ("commands", &available.join(", ")[..])]))
     }
 }
 
@@ -46,57 +308,80 @@ impl fmt::Debug for Command {
 //                     CommandHandler                     //
 ////////////////////////////////////////////////////////////
 
+/// CommandHandler
+/// jobs:       Commands running in the background
+/// templates:  Texts of the replies the bot makes on its own
+/// pending:    Commands waiting for their user to say please, by (user, issue number)
+/// started_at: Unix timestamp at which the bot started handling commands
 #[derive(Debug)]
 pub struct CommandHandler {
-    config:   Arc<Mutex<config::ConfigHandler>>,
-    commands: BTreeMap<String, Command>
+    config:      Arc<Mutex<config::ConfigHandler>>,
+    outbound:    outbound::OutboundQueue,
+    permissions: permissions::PermissionCache,
+    registry:    Arc<CommandRegistry>,
+    jobs:        jobs::JobManager,
+    templates:   templates::Templates,
+    pending:     Mutex<BTreeMap<(String, u64), PendingCommand>>,
+    started_at:  i64
 }
 
 impl CommandHandler {
 
-    pub fn new(tsconfig: &Arc<Mutex<config::ConfigHandler>>) -> CommandHandler {
+    pub fn new(tsconfig: &Arc<Mutex<config::ConfigHandler>>, outbound: &outbound::OutboundQueue) -> CommandHandler {
 
-        thread_trace!("commands.rs: CommandHandler::new(tsconfig)");
+        thread_trace!("commands.rs: CommandHandler::new(tsconfig, outbound)");
 
-        //Register commands
-        let mut commands: BTreeMap<String, Command> = BTreeMap::new();
-        commands.insert(String::from("ping"), Command::new(false, false, ping));
-        commands.insert(String::from("help"), Command::new(false, false, help));
+        //Validated along with the rest of the config at startup
+        let templates = match templates::Templates::from_config(&mut tsconfig.lock().unwrap()) {
+            Ok(templates) => templates,
+            Err(err)      => {thread_crash!("Error loading the templates of the config: {}", err);}
+        };
 
         CommandHandler {
-            config:   tsconfig.clone(),
-            commands: commands
+            config:      tsconfig.clone(),
+            outbound:    outbound.clone(),
+            permissions: permissions::PermissionCache::new(tsconfig),
+            registry:    Arc::new(CommandRegistry::builtin()),
+            jobs:        jobs::JobManager::new(tsconfig),
+            templates:   templates,
+            pending:     Mutex::new(BTreeMap::new()),
+            started_at:  UTC::now().timestamp()
         }
     }
 
-    pub fn parse_command(&self, webhook: webhooks::WebhookEvent) {
-
-        thread_trace!("commands.rs: CommandHandler::parse_command(&self, webhook)");
+    //Adds a command to the built-in ones, before any command is run
+    pub fn register(&mut self, name: &str, command: Command) {
+        match Arc::get_mut(&mut self.registry) {
+            Some(registry) => registry.register(name, command),
+            None           => {thread_crash!("Commands can only be registered before the bot starts running them.");}
+        }
+    }
 
-        let mut tokens: Vec<&str>   = webhook.command.split_whitespace().collect();
-        let mut is_please_provided  = false;
-        let mut is_user_whitelisted = false;
-        let mut next_token_index    = 0;
-        let mut bot_name            = String::new();
+    //Comments carry the commands, the other events are followed for the
+    //automerge and the merge queue
+    pub fn handle_event(&self, webhook: webhooks::WebhookEvent) {
+        match webhook.event_type {
+            webhooks::WebhookEventType::IssueComment       |
+            webhooks::WebhookEventType::PullRequestComment => self.parse_command(webhook),
+            _                                              => {
+                automerge::on_event(&self.config, &self.outbound, &self.permissions, &webhook);
+                queue::on_event(&self.config, &self.outbound, &webhook);
+            }
+        }
+    }
 
-        thread_debug!("Command tokens: {:?}", tokens);
+    //Runs every command of the webhook in order, the replies are combined into
+    //one comment listing the outcome of each command
+    pub fn parse_command(&self, webhook: webhooks::WebhookEvent) {
 
-        //Check if please was said
-        if tokens[0].to_lowercase() == "please" {
-            is_please_provided = true;
-            next_token_index   = 1;
-        }
-        thread_debug!("Please was said: {}", is_please_provided);
+        thread_trace!("commands.rs: CommandHandler::parse_command(&self, webhook)");
 
-        //Check if user is whitelisted for restricted commands
-        //  Restricted scope, we are using the config here but
-        //  the callback can also use it so we force the mutex
-        //  to unlock before letting the callback lock it
-        //  given that locking twice will induce a panic.
+        let bot_name:           String;
+        let github_follow_repo: String;
         {
-            let mut config      = self.config.lock().unwrap();
-            is_user_whitelisted = config.whitelist_validate_user(webhook.clone().user);
-            bot_name            = config.get_string_required("config", "github_bot_name");
+            let mut config     = self.config.lock().unwrap();
+            bot_name           = config.get_string_required("config", "github_bot_name");
+            github_follow_repo = config.get_string_required("config", "github_follow_repo");
         }
 
         //Ignore commands/responses from the bot
@@ -106,56 +391,407 @@ impl CommandHandler {
             return;
         }
 
+        //Let the user know the comment was seen
+        let client = github::GithubClient::bot(&self.config);
+        let eyes   = self.react(&client, &github_follow_repo, &webhook, "eyes");
+
+        let mut statuses: Vec<CommandStatus>            = Vec::new();
+        let mut outcomes: Vec<(String, CommandOutcome)> = Vec::new();
+        for command_line in webhook.commands.iter() {
+            thread_debug!("Running command: {}", command_line);
+            let started = UTC::now();
+            let outcome = self.run_command(&webhook, command_line);
+            self.audit(&webhook, command_line, &outcome, started);
+            statuses.push(outcome.status);
+
+            //Nothing to say, i.e the command replied on its own
+            if outcome.message.is_empty() {
+                continue;
+            }
+
+            //Sticky replies are comments of their own
+            if outcome.message.sticky_tag().is_some() {
+                respond(&self.config, &self.outbound, webhook.clone(), outcome.message);
+                continue;
+            }
+            outcomes.push((command_line.clone(), outcome));
+        }
+
+        if outcomes.len() == 1 {
+            let (_, outcome) = outcomes.remove(0);
+            respond(&self.config, &self.outbound, webhook.clone(), outcome.message);
+        } else if outcomes.len() > 1 {
+            let mut reply = Message::new();
+            for (command_line, outcome) in outcomes {
+                reply = reply
+                    .paragraph(&format!("{} {}", outcome.status.emoji(), response::inline_code(&command_line)))
+                    .append(outcome.message);
+            }
+            respond(&self.config, &self.outbound, webhook.clone(), reply);
+        }
+
+        //Swap :eyes: for the result, commands waiting for a please are not done
+        //yet and jobs are still running
+        let failed = statuses.iter().any(|status| *status == CommandStatus::Failed || *status == CommandStatus::Rejected);
+        if !failed && (statuses.iter().all(|status| *status == CommandStatus::Pending) || statuses.contains(&CommandStatus::Started)) {
+            return;
+        }
+        let result = if failed {"confused"} else {"+1"};
+        if let Some(reaction_id) = eyes {
+            if let Err(err) = reactions::remove(&client, &github_follow_repo, &webhook, reaction_id) {
+                thread_error!("Failed to remove the :eyes: reaction: {}", err);
+            }
+        }
+        self.react(&client, &github_follow_repo, &webhook, result);
+    }
+
+    //The audit log is a record, failing to write to it does not stop the commands
+    //The role is the one the permission check resolved, no lookup is made for the log
+    fn audit(&self, webhook: &webhooks::WebhookEvent, command_line: &str, outcome: &CommandOutcome, started: DateTime<UTC>) {
+        let entry = audit::AuditEntry {
+            at:          started.timestamp(),
+            delivery:    webhook.delivery.clone(),
+            user:        webhook.user.clone(),
+            role:        outcome.role.clone().unwrap_or(String::from("Unknown")),
+            number:      webhook.number,
+            command:     String::from(command_line),
+            status:      format!("{:?}", outcome.status),
+            duration_ms: cmp::max(0, (UTC::now() - started).num_milliseconds()) as u64
+        };
+        if let Err(err) = audit::record(&self.config, &entry) {
+            thread_error!("{}", err);
+        }
+    }
+
+    //Reactions are a courtesy, failing to add one does not stop the commands
+    fn react(&self, client: &github::GithubClient, repo: &str, webhook: &webhooks::WebhookEvent, content: &str) -> Option<u64> {
+        if webhook.comment_id == 0 {
+            return None
+        }
+        match reactions::add(client, repo, webhook, content) {
+            Ok(reaction_id) => Some(reaction_id),
+            Err(err)        => {
+                thread_error!("Failed to add the :{}: reaction: {}", content, err);
+                None
+            }
+        }
+    }
+
+    fn run_command(&self, webhook: &webhooks::WebhookEvent, command_line: &String) -> CommandOutcome {
+
+        thread_trace!("commands.rs: CommandHandler::run_command(&self, webhook, \"{}\")", command_line);
+
+        let mut is_please_provided = false;
+
+        //Split the command into arguments
+        thread_trace!("Tokenize command.");
+        let mut tokens = match args::tokenize(command_line) {
+            Ok(tokens) => tokens,
+            Err(err)   => {
+                thread_trace!("Command could not be tokenized.");
+                return CommandOutcome::new(CommandStatus::Rejected, Message::text(&self.templates.render("unparsable", &[("user", &webhook.user[..]), ("command", &command_line[..]), ("error", &err[..])])));
+            }
+        };
+
+        //Check if please was said
+        if tokens.first().map(|token| token.to_lowercase() == "please").unwrap_or(false) {
+            is_please_provided = true;
+            tokens.remove(0);
+        }
+        thread_debug!("Please was said: {}", is_please_provided);
+
+        //A bare please runs the command waiting for it
+        if is_please_provided && tokens.is_empty() {
+            return self.run_pending_command(webhook);
+        }
+
+        let command_args = match args::CommandArgs::from_tokens(tokens) {
+            Ok(command_args) => command_args,
+            Err(_)           => {
+                thread_trace!("Command is empty.");
+                return CommandOutcome::new(CommandStatus::Rejected, Message::text(&self.templates.render("empty_command", &[("user", &webhook.user[..])])));
+            }
+        };
+        thread_debug!("Command arguments: {:?}", command_args);
+
         //Find command among registered commands
         thread_trace!("Check if command exists.");
-        thread_debug!("Looking for command: {}", tokens[next_token_index]);
-        let command = match self.commands.get(tokens[next_token_index]) {
-            Some(command) => {
+        thread_debug!("Looking for command: {}", command_args.name);
+        let (command_name, command) = match self.registry.find(&command_args.name) {
+            Some((command_name, command)) => {
                 thread_trace!("Command exists.");
-                command
+                (command_name, command)
             },
-            None          => {
-                thread_trace!("Command does not exists, send response.");
-                respond(&self.config, webhook.clone(), String::from("Sorry the command was not found. Please visit [https://hunterbot.readthedocs.io](https://hunterbot.readthedocs.io) for available commands."));
-                return;
+            None                          => {
+                thread_trace!("Command does not exists.");
+                let user_role   = self.permissions.user_role(webhook, Role::Owner);
+                let whitelisted = self.permissions.is_whitelisted(webhook);
+                return CommandOutcome::new(CommandStatus::Rejected, self.registry.command_not_found(&command_args.name, user_role, whitelisted, &self.templates))
+                    .role(user_role, whitelisted);
             }
         };
 
-        //Check if please and whitelist is required
-        let mut run_cmd         = false;
-        let mut response_prefix = String::new();
-
-        thread_trace!("Check for whitelist and please.");
-        thread_debug!("is_please_provided:  {}", is_please_provided);
-        thread_debug!("is_user_whitelisted: {}", is_user_whitelisted);
-
-        if !(command.whitelist_only && !is_user_whitelisted) {
-            if (command.requires_please && is_please_provided) || (!command.requires_please && !is_please_provided) {
-                run_cmd = true;
-            } else if !command.requires_please && is_please_provided {
-                run_cmd         = true;
-                response_prefix = String::from("You didn't need to say please but thanks anyways :smiley: \\r\\n\\r\\nOhh and: \\r\\n");
-            } else if command.requires_please && !is_please_provided {
-                //TODO: keep please state
-                respond(&self.config, webhook.clone(), String::from("Whats the magic word?"));
-            }
-        } else {
-            respond(&self.config, webhook.clone(), String::from("Sorry! That command if for whitelisted people only!"));
+        //Check if please and a role is required
+        let mut response_prefix = Message::new();
+
+        thread_trace!("Check for role and please.");
+        thread_debug!("is_please_provided: {}", is_please_provided);
+        thread_debug!("Required role:      {:?}", command.role);
+
+        let user_role   = self.permissions.user_role(webhook, command.role);
+        let whitelisted = self.permissions.is_whitelisted(webhook);
+        if !command.allows(user_role, whitelisted) {
+            return CommandOutcome::new(CommandStatus::Rejected, Message::text(&self.templates.render("not_allowed", &[("user", &webhook.user[..]), ("command", &command_name[..]), ("role", &command.requirement()[..])])))
+                .role(user_role, whitelisted);
+        }
+        let requires_please = self.requires_please(&command_name, command);
+        if requires_please && !is_please_provided {
+            let timeout = self.please_timeout();
+            self.pending.lock().unwrap().insert((webhook.user.clone(), webhook.number), PendingCommand {
+                command_line: command_line.clone(),
+                expires_at:   UTC::now().timestamp() + timeout
+            });
+            thread_debug!("Command waiting for please: {}", command_line);
+            let minutes = ((timeout + 59) / 60).to_string();
+            return CommandOutcome::new(CommandStatus::Pending, Message::text(&self.templates.render("please_required", &[("user", &webhook.user[..]), ("command", &command_name[..]), ("minutes", &minutes[..])])))
+                .role(user_role, whitelisted);
+        }
+        if !requires_please && is_please_provided {
+            response_prefix = Message::text(&self.templates.render("please_not_required", &[("user", &webhook.user[..]), ("command", &command_name[..])]));
         }
 
-        thread_debug!("run_cmd: {}", run_cmd);
+        //The config is locked only long enough to take a snapshot, commands can lock it again
+        let config_snapshot = self.config.lock().unwrap().clone();
+        let mut context     = CommandContext {
+            config:      config_snapshot,
+            tsconfig:    self.config.clone(),
+            github:      github::GithubClient::bot(&self.config),
+            store:       store::Store::new(&self.config),
+            permissions: self.permissions.clone(),
+            registry:    self.registry.clone(),
+            jobs:        self.jobs.clone(),
+            templates:   self.templates.clone(),
+            outbound:    self.outbound.clone(),
+            started_at:  self.started_at,
+            job:         None,
+            event:       webhook.clone(),
+            args:        command_args,
+            replies:     ReplySink::new(&self.config, &self.outbound, webhook)
+        };
 
-        if run_cmd {
-            match (command.callback)(&self.config, webhook.clone(), tokens.split_off(next_token_index)) {
-                Ok(msg)  => {
-                    respond(&self.config, webhook.clone(), format!("{}{}", response_prefix, msg));
-                }
-                Err(msg) => {
-                    respond(&self.config, webhook.clone(), format!("An error occurred while executing the command: {}", msg));
+        //Jobs report in a comment of their own
+        if let Some(default_timeout) = command.timeout {
+            let timeout = self.job_timeout(&command_name, default_timeout);
+            self.jobs.start(command_line, timeout, command.handler.clone(), context);
+            return CommandOutcome::new(CommandStatus::Started, response_prefix).role(user_role, whitelisted);
+        }
+
+        let outcome = match command.handler.run(&mut context) {
+            Ok(msg)  => CommandOutcome::new(CommandStatus::Succeeded, response_prefix.append(msg)),
+            Err(msg) => CommandOutcome::new(CommandStatus::Failed, Message::text(&self.templates.render("command_failed", &[("user", &webhook.user[..]), ("command", &command_name[..]), ("error", &msg[..])])))
+        };
+        outcome.role(user_role, whitelisted)
+    }
+
+    //Runs the command the user was asked to say please for on this issue
+    fn run_pending_command(&self, webhook: &webhooks::WebhookEvent) -> CommandOutcome {
+
+        thread_trace!("commands.rs: CommandHandler::run_pending_command(&self, webhook)");
+
+        let pending: Option<PendingCommand>;
+        {
+            let     now     = UTC::now().timestamp();
+            let mut waiting = self.pending.lock().unwrap();
+            let expired: Vec<(String, u64)> = waiting.iter()
+                .filter(|&(_, command)| command.expires_at <= now)
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in expired {
+                waiting.remove(&key);
+            }
+            pending = waiting.remove(&(webhook.user.clone(), webhook.number));
+        }
+
+        match pending {
+            Some(pending) => {
+                thread_debug!("Running the command waiting for please: {}", pending.command_line);
+                let outcome = self.run_command(webhook, &format!("please {}", pending.command_line));
+                CommandOutcome {
+                    status:  outcome.status,
+                    message: Message::text(&self.templates.render("please_accepted", &[("user", &webhook.user[..]), ("command", &pending.command_line[..])])).append(outcome.message),
+                    role:    outcome.role
                 }
             }
+            None          => CommandOutcome::new(CommandStatus::Rejected, Message::text(&self.templates.render("please_nothing_pending", &[("user", &webhook.user[..])])))
         }
     }
+
+    //The "please" section of the config overrides whether a command needs a please, i.e `deadletters = "true"`
+    fn requires_please(&self, command_name: &str, command: &Command) -> bool {
+        let mut config = self.config.lock().unwrap();
+        match config.get_string("please", command_name) {
+            Ok(value) => value == "true",
+            Err(_)    => command.requires_please
+        }
+    }
+
+    //The "timeouts" section of the config overrides the timeout of a job, i.e `download = "3600"`
+    fn job_timeout(&self, command_name: &str, default_timeout: u64) -> u64 {
+        let mut config = self.config.lock().unwrap();
+        match config.get_string("timeouts", command_name) {
+            Ok(value) => value.parse().unwrap_or(default_timeout),
+            Err(_)    => default_timeout
+        }
+    }
+
+    //Seconds a command waits for its please ("please_timeout", 10 minutes by default)
+    fn please_timeout(&self) -> i64 {
+        let mut config = self.config.lock().unwrap();
+        config.get_string_or("config", "please_timeout", "600").parse().unwrap_or(600)
+    }
+}
+
+/// PendingCommand
+/// command_line: Command as it was given, without please
+/// expires_at:   Unix timestamp after which please no longer runs it
+#[derive(Clone, Debug)]
+pub struct PendingCommand {
+    pub command_line: String,
+    pub expires_at:   i64
+}
+
+
+////////////////////////////////////////////////////////////
+//                     CommandContext                     //
+////////////////////////////////////////////////////////////
+
+/// CommandContext
+/// config:      Snapshot of the config taken when the command started
+/// tsconfig:    The shared config, for commands that change it
+/// github:      Client acting as the bot
+/// store:       State kept in "data_dir"
+/// permissions: Roles of the users
+/// registry:    Registered commands
+/// jobs:        Commands running in the background
+/// templates:   Texts of the replies the bot makes on its own
+/// outbound:    Replies waiting to be delivered
+/// started_at:  Unix timestamp at which the bot started handling commands
+/// job:         The job running this command, if it runs in the background
+/// event:       Event the command came from
+/// args:        Arguments of the command
+/// replies:     Posts replies before the command is done, i.e to report progress
+pub struct CommandContext {
+    pub config:      config::ConfigHandler,
+    pub tsconfig:    Arc<Mutex<config::ConfigHandler>>,
+    pub github:      github::GithubClient,
+    pub store:       store::Store,
+    pub permissions: permissions::PermissionCache,
+    pub registry:    Arc<CommandRegistry>,
+    pub jobs:        jobs::JobManager,
+    pub templates:   templates::Templates,
+    pub outbound:    outbound::OutboundQueue,
+    pub started_at:  i64,
+    pub job:         Option<jobs::JobHandle>,
+    pub event:       webhooks::WebhookEvent,
+    pub args:        args::CommandArgs,
+    pub replies:     ReplySink
+}
+
+impl CommandContext {
+
+    //Reports progress when running as a job
+    pub fn progress(&self, progress: &str) {
+        if let Some(ref job) = self.job {
+            job.progress(&self.replies, progress);
+        }
+    }
+
+    //Set when the job was cancelled or timed out, the command should return as soon as it can
+    pub fn is_cancelled(&self) -> bool {
+        match self.job {
+            Some(ref job) => job.is_cancelled(),
+            None          => false
+        }
+    }
+}
+
+///Replies to the issue or PR an event came from
+#[derive(Clone, Debug)]
+pub struct ReplySink {
+    config:   Arc<Mutex<config::ConfigHandler>>,
+    outbound: outbound::OutboundQueue,
+    event:    webhooks::WebhookEvent
+}
+
+impl ReplySink {
+
+    pub fn new(tsconfig: &Arc<Mutex<config::ConfigHandler>>, outbound: &outbound::OutboundQueue, event: &webhooks::WebhookEvent) -> ReplySink {
+        ReplySink {
+            config:   tsconfig.clone(),
+            outbound: outbound.clone(),
+            event:    event.clone()
+        }
+    }
+
+    pub fn send(&self, msg: Message) {
+        respond(&self.config, &self.outbound, self.event.clone(), msg);
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                     CommandOutcome                     //
+////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CommandStatus {
+    Succeeded,
+    Failed,
+    //Not run: unknown command, invalid arguments or not allowed
+    Rejected,
+    //Not run yet: waiting for the user to say please
+    Pending,
+    //Running as a background job, which reports in a comment of its own
+    Started
+}
+
+impl CommandStatus {
+    pub fn emoji(&self) -> &'static str {
+        match *self {
+            CommandStatus::Succeeded => ":white_check_mark:",
+            CommandStatus::Failed    => ":x:",
+            CommandStatus::Rejected  => ":no_entry_sign:",
+            CommandStatus::Pending   => ":hourglass:",
+            CommandStatus::Started   => ":hourglass_flowing_sand:"
+        }
+    }
+}
+
+/// CommandOutcome
+/// status:  How the command went
+/// message: Reply to the user
+/// role:    Role the permission check found, i.e "Write" or "Anyone+Whitelisted",
+///          None if the command was rejected before it was checked
+#[derive(Clone, Debug)]
+pub struct CommandOutcome {
+    pub status:  CommandStatus,
+    pub message: Message,
+    pub role:    Option<String>
+}
+
+impl CommandOutcome {
+    pub fn new(status: CommandStatus, message: Message) -> CommandOutcome {
+        CommandOutcome {
+            status:  status,
+            message: message,
+            role:    None
+        }
+    }
+
+    pub fn role(mut self, role: Role, whitelisted: bool) -> CommandOutcome {
+        self.role = Some(if whitelisted {format!("{:?}+Whitelisted", role)} else {format!("{:?}", role)});
+        self
+    }
 }
 
 
@@ -163,14 +799,57 @@ impl CommandHandler {
 //                        Callbacks                       //
 ////////////////////////////////////////////////////////////
 
-#[allow(unused_variables)]
-pub fn ping(tsconfig: &Arc<Mutex<config::ConfigHandler>>, raw_event: webhooks::WebhookEvent, args: Vec<&str>) -> Result<String, String> {
-    return Ok(String::from("Pong"))
+pub fn ping(_context: &mut CommandContext) -> Result<Message, String> {
+    thread_trace!("commands.rs: ping(context)");
+    Ok(Message::text("Pong"))
+}
+
+//"help" lists the commands the user can run, "help <command>" shows how to use one
+pub fn help(context: &mut CommandContext) -> Result<Message, String> {
+
+    thread_trace!("commands.rs: help(context)");
+
+    if let Some(name) = context.args.positional(0) {
+        return match context.registry.find(name) {
+            Some((command_name, command)) => Ok(help::command_usage(&command_name, command)),
+            None                          => Err(format!("There is no command named {}.", response::inline_code(name)))
+        }
+    }
+
+    let user_role   = context.permissions.user_role(&context.event, Role::Owner);
+    let whitelisted = context.permissions.is_whitelisted(&context.event);
+    Ok(help::command_table(&context.registry, user_role, whitelisted, &context.templates))
 }
 
-#[allow(unused_variables)]
-pub fn help(tsconfig: &Arc<Mutex<config::ConfigHandler>>, raw_event: webhooks::WebhookEvent, args: Vec<&str>) -> Result<String, String> {
-    return Ok(String::from("Documentation related to the bot including available commands are at [https://hunterbot.readthedocs.io](https://hunterbot.readthedocs.io)"))
+//Lists the replies the bot gave up on delivering, "deadletters [count]"
+pub fn deadletters(context: &mut CommandContext) -> Result<Message, String> {
+
+    thread_trace!("commands.rs: deadletters(context)");
+
+    let count: usize = match context.args.positional(0) {
+        Some(count) => try!(count.parse().map_err(|_| format!("{} is not a valid count.", response::inline_code(count)))),
+        None        => 10
+    };
+
+    let dead_letters = try!(outbound::dead_letters(&context.tsconfig));
+    if dead_letters.is_empty() {
+        return Ok(Message::text("No undelivered messages."))
+    }
+
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    for &(failed_at, ref message) in dead_letters.iter().rev().take(count) {
+        let excerpt: String = message.comment_body().chars().take(80).collect();
+        rows.push(vec![
+            response::inline_code(&message.endpoint),
+            UTC.timestamp(failed_at, 0).format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+            message.attempts.to_string(),
+            message.last_error.clone(),
+            response::inline_code(&excerpt.replace("\r", "").replace("\n", " "))
+        ]);
+    }
+
+    Ok(Message::text(&format!("{} undelivered message(s), showing the last {}:", dead_letters.len(), rows.len()))
+        .table(vec!["Endpoint", "Given up at", "Attempts", "Last error", "Message"], rows))
 }
 
 
@@ -178,24 +857,98 @@ pub fn help(tsconfig: &Arc<Mutex<config::ConfigHandler>>, raw_event: webhooks::W
 //                          Utils                         //
 ////////////////////////////////////////////////////////////
 
-pub fn respond(tsconfig: &Arc<Mutex<config::ConfigHandler>>, raw_event: webhooks::WebhookEvent, msg: String) {
+//Levenshtein distance, in characters
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    let mut previous: Vec<usize> = (0..b.len() + 1).collect();
+    let mut current:  Vec<usize> = vec![0; b.len() + 1];
 
-    thread_trace!("commands.rs: respond(tsconfig, raw_event, msg)");
+    for i in 0..a.len() {
+        current[0] = i + 1;
+        for j in 0..b.len() {
+            let substitution = previous[j] + if a[i] == b[j] {0} else {1};
+            current[j + 1]   = cmp::min(substitution, cmp::min(previous[j + 1] + 1, current[j] + 1));
+        }
+        previous.clone_from(&current);
+    }
+    previous[b.len()]
+}
+
+//Replies are delivered by the outbound queue, which retries on failure.
+//Replies longer than a comment allows are split into numbered comments
+//("comment_overflow" = "split", the default) or, past "comment_max_parts"
+//parts or with "comment_overflow" = "truncate", cut short with a link to
+//the full text in the archive.
+pub fn respond(tsconfig: &Arc<Mutex<config::ConfigHandler>>, outbound: &outbound::OutboundQueue, raw_event: webhooks::WebhookEvent, msg: Message) {
+
+    thread_trace!("commands.rs: respond(tsconfig, outbound, raw_event, msg)");
 
     //TODO: Don't forget to change this when refactoring config
     //Get repo were following
     let github_follow_repo: String;
-    let github_bot_token: String;
+    let comment_overflow:   String;
+    let comment_max_parts:  usize;
     {
-        let mut config = tsconfig.lock().unwrap();
+        let mut config     = tsconfig.lock().unwrap();
         github_follow_repo = config.get_string_required("config", "github_follow_repo");
-        github_bot_token = config.get_string_required("config", "github_bot_token");
+        comment_overflow   = config.get_string_or("config", "comment_overflow", "split");
+        comment_max_parts  = config.get_string_or("config", "comment_max_parts", "5").parse().unwrap_or(5);
+    }
+
+    let endpoint   = format!("repos/{}/issues/{}/comments", github_follow_repo, raw_event.number);
+    let sticky_tag = msg.sticky_tag();
+    let markdown   = msg.mention(&raw_event.user).to_markdown();
+
+    //Sticky comments are edited in place, there is a single one to update
+    if let Some(tag) = sticky_tag {
+        let body = sticky::with_marker(&fit_comment(tsconfig, markdown, &String::from("truncate"), 1)[0], &tag);
+        match response::comment_json(&body) {
+            Ok(body) => outbound.push_sticky(endpoint, tag, body),
+            Err(err) => {thread_error!("{}", err);}
+        }
+        return;
+    }
+
+    for body in fit_comment(tsconfig, markdown, &comment_overflow, comment_max_parts) {
+        match response::comment_json(&body) {
+            Ok(body) => outbound.push(endpoint.clone(), body),
+            Err(err) => {thread_error!("{}", err);}
+        }
+    }
+}
+
+//Room left in every part for the "(1/3)" header or the truncation note
+const OVERFLOW_RESERVE: usize = 512;
+
+fn fit_comment(tsconfig: &Arc<Mutex<config::ConfigHandler>>, markdown: String, comment_overflow: &String, comment_max_parts: usize) -> Vec<String> {
+
+    thread_trace!("commands.rs: fit_comment(tsconfig, markdown, \"{}\", {})", comment_overflow, comment_max_parts);
+
+    if markdown.chars().count() <= response::MAX_COMMENT_LENGTH {
+        return vec![markdown]
     }
 
-    let endpoint = format!("repos/{}/issues/{}/comments?access_token={}", github_follow_repo, raw_event.number, github_bot_token);
-    let message  = format!("{{\"body\": \"@{} {}\"}}", raw_event.user, msg);
-    match webhooks::github_post_request(endpoint, message) {
-        Ok(())   => (),
-        Err(err) => {thread_error!("{}", err);}
+    let limit = response::MAX_COMMENT_LENGTH - OVERFLOW_RESERVE;
+    if comment_overflow != "truncate" {
+        let parts = response::split_markdown(&markdown, limit);
+        if parts.len() <= comment_max_parts {
+            thread_debug!("Splitting an oversized response into {} comments", parts.len());
+            let count = parts.len();
+            return parts.into_iter().enumerate()
+                .map(|(index, part)| format!("**({}/{})**\n\n{}", index + 1, count, part))
+                .collect()
+        }
     }
+
+    thread_debug!("Truncating an oversized response");
+    let note = match archive::store(tsconfig, &markdown) {
+        Ok(url)  => format!("*The response was too long for a comment and was truncated, the full text is at {}*", url),
+        Err(err) => {
+            thread_error!("Failed to archive an oversized response: {}", err);
+            String::from("*The response was too long for a comment and was truncated.*")
+        }
+    };
+    vec![format!("{}\n\n{}", response::truncate_markdown(&markdown, limit), note)]
 }
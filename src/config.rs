@@ -8,16 +8,25 @@ use std::path::PathBuf;
 
 extern crate toml;
 
+use templates;
+
 include!("logger_macros.rs");
 
 ////////////////////////////////////////////////////////////
 //                     ConfigHandler                      //
 ////////////////////////////////////////////////////////////
 
+/// ConfigHandler
+/// toml_data: Content of the config file
+/// file_path: Where the config is saved
+/// dry_run:   Set by --dry-run, not saved in the config file
+/// log_dir:   Set by --log-dir, not saved in the config file
 #[derive(Clone, Debug)]
 pub struct ConfigHandler {
     pub toml_data: toml::Table,
-    pub file_path: PathBuf
+    pub file_path: PathBuf,
+    pub dry_run:   bool,
+    pub log_dir:   PathBuf
 }
 
 impl ConfigHandler {
@@ -26,7 +35,9 @@ impl ConfigHandler {
         thread_trace!("config.rs: ConfigHandler::new()");
         ConfigHandler {
             toml_data: toml::Table::new(),
-            file_path: PathBuf::new()
+            file_path: PathBuf::new(),
+            dry_run:   false,
+            log_dir:   PathBuf::from("./")
         }
     }
 
@@ -181,6 +192,11 @@ impl ConfigHandler {
         //Ok to be empty
         let whitelist = self.get_array_required("config", "whitelist");
 
+        //Only the templates that are set replace the built-in ones
+        if let Err(err) = templates::Templates::from_config(self) {
+            thread_crash!("Invalid template in the config: {}", err);
+        }
+
         thread_info!("Config validation passed.");
         thread_debug!("Config value \"github_bot_name\" =    \"{}\"", github_bot_name);
         thread_debug!("Config value \"github_bot_token\" =   \"{}\"", github_bot_token);
@@ -362,6 +378,34 @@ impl ConfigHandler {
         }
     }
 
+    //Same as get_string() but falls back to a default value for optional keys
+    pub fn get_string_or(&mut self, section: &str, key: &str, default: &str) -> String {
+        thread_trace!("config.rs: ConfigHandler::get_string_or(&mut self, \"{}\", \"{}\", \"{}\")", section, key, default);
+        match self.get_string(&section, &key) {
+            Ok(value) => {
+                thread_trace!("Return value");
+                return value
+            },
+            Err(_)    => {
+                thread_trace!("Return default");
+                return String::from(default)
+            }
+        }
+    }
+
+    //Path of a file in the directory where the bot keeps its state ("data_dir", defaults to "./")
+    pub fn get_data_file_path(&mut self, file_name: &str) -> PathBuf {
+        thread_trace!("config.rs: ConfigHandler::get_data_file_path(&mut self, \"{}\")", file_name);
+        let mut path = PathBuf::from(self.get_string_or("config", "data_dir", "./"));
+        path.push(file_name);
+        path
+    }
+
+    //Mutating Github calls are logged instead of made, by --dry-run or `dry_run = "true"`
+    pub fn is_dry_run(&mut self) -> bool {
+        self.dry_run || self.get_string_or("config", "dry_run", "false") == "true"
+    }
+
     //Is the user in the whitelist?
     pub fn whitelist_validate_user(&mut self, user: String) -> bool {
 
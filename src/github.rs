@@ -0,0 +1,221 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+
+extern crate chrono;
+use self::chrono::*;
+
+extern crate hyper;
+use hyper::Client;
+use hyper::client::Body;
+use hyper::client::IntoUrl;
+use hyper::header::Headers;
+use hyper::method::Method;
+
+extern crate serde_json;
+
+use config;
+use store;
+use webhooks;
+
+include!("logger_macros.rs");
+
+const DRY_RUN_REPORT_FILE_NAME: &'static str = "dry-run.json";
+
+
+////////////////////////////////////////////////////////////
+//                     GithubClient                       //
+////////////////////////////////////////////////////////////
+
+///Client for the Github API authenticated with one of the configured tokens.
+///Endpoints are relative to "github_api_url", i.e "repos/{repo}/issues/1/comments".
+///In a dry run, calls other than GET are written to the log and to
+///"data_dir"/dry-run.json instead of being made.
+#[derive(Clone)]
+pub struct GithubClient {
+    api_url: String,
+    token:   String,
+    dry_run: Option<store::Store>
+}
+
+impl GithubClient {
+
+    pub fn new(api_url: String, token: String) -> GithubClient {
+        GithubClient {
+            api_url: api_url,
+            token:   token,
+            dry_run: None
+        }
+    }
+
+    //Client acting as the bot ("github_bot_token")
+    pub fn bot(tsconfig: &Arc<Mutex<config::ConfigHandler>>) -> GithubClient {
+        let mut config = tsconfig.lock().unwrap();
+        let token      = config.get_string_required("config", "github_bot_token");
+        GithubClient::from_config(&mut config, token)
+    }
+
+    //Client acting as the repository owner ("github_owner_token")
+    pub fn owner(tsconfig: &Arc<Mutex<config::ConfigHandler>>) -> GithubClient {
+        let mut config = tsconfig.lock().unwrap();
+        let token      = config.get_string_required("config", "github_owner_token");
+        GithubClient::from_config(&mut config, token)
+    }
+
+    fn from_config(config: &mut config::ConfigHandler, token: String) -> GithubClient {
+        let mut client = GithubClient::new(webhooks::github_api_url(config), token);
+        if config.is_dry_run() {
+            client.dry_run = Some(store::Store::from_config(config));
+        }
+        client
+    }
+
+    pub fn get(&self, endpoint: &str) -> Result<serde_json::Value, String> {
+        self.request_ok(Method::Get, endpoint, None)
+    }
+
+    //Follows the pagination of list endpoints and concatenates the pages
+    pub fn get_all(&self, endpoint: &str) -> Result<Vec<serde_json::Value>, String> {
+
+        thread_trace!("github.rs: GithubClient::get_all(&self, {})", endpoint);
+
+        let mut items: Vec<serde_json::Value> = Vec::new();
+        let mut page                          = 1;
+        loop {
+            let page_endpoint = format!("{}{}per_page=100&page={}", endpoint, query_separator(endpoint), page);
+            let value         = try!(self.get(&page_endpoint));
+            let array         = try!(value.as_array().ok_or(format!("Expected a list from {}.", endpoint))).clone();
+            if array.is_empty() {
+                break;
+            }
+            let is_last_page = array.len() < 100;
+            items.extend(array);
+            if is_last_page {
+                break;
+            }
+            page += 1;
+        }
+
+        thread_trace!("Return Ok({} item(s))", items.len());
+        Ok(items)
+    }
+
+    pub fn post(&self, endpoint: &str, body: &str) -> Result<serde_json::Value, String> {
+        self.request_ok(Method::Post, endpoint, Some(body))
+    }
+
+    pub fn patch(&self, endpoint: &str, body: &str) -> Result<serde_json::Value, String> {
+        self.request_ok(Method::Patch, endpoint, Some(body))
+    }
+
+    pub fn put(&self, endpoint: &str, body: &str) -> Result<serde_json::Value, String> {
+        self.request_ok(Method::Put, endpoint, Some(body))
+    }
+
+    pub fn delete(&self, endpoint: &str) -> Result<serde_json::Value, String> {
+        self.request_ok(Method::Delete, endpoint, None)
+    }
+
+    //Same as request() but any status other than 2xx is an error
+    fn request_ok(&self, method: Method, endpoint: &str, body: Option<&str>) -> Result<serde_json::Value, String> {
+        let (status, value) = try!(self.request(method.clone(), endpoint, body));
+        if status / 100 != 2 {
+            let message = value.find("message").and_then(|message| message.as_str()).unwrap_or("").to_string();
+            return Err(format!("{} {} failed with status {}: {}", method, endpoint, status, message))
+        }
+        Ok(value)
+    }
+
+    //Ok: (status code, parsed body), Null if the body is empty
+    //Err: The request could not be made
+    pub fn request(&self, method: Method, endpoint: &str, body: Option<&str>) -> Result<(u16, serde_json::Value), String> {
+
+        thread_trace!("github.rs: GithubClient::request(&self, {}, {}, {:?})", method, endpoint, body);
+
+        if let Some(ref report) = self.dry_run {
+            if method != Method::Get && method != Method::Head {
+                return dry_run_request(report, method, endpoint, body)
+            }
+        }
+
+        let     http_client = Client::new();
+        let     api_call    = format!("{}/{}{}access_token={}", self.api_url, endpoint, query_separator(endpoint), self.token);
+        let mut header      = Headers::new();
+
+        thread_trace!("  Api call to url");
+        let api_call_url = match api_call.into_url() {
+            Ok(url)  => url,
+            Err(err) => {
+                thread_trace!("Return Err");
+                return Err(format!("Failed to parse the API call url: {}", err))
+            }
+        };
+
+        header.set_raw("User-Agent", vec![b"hunter-bot".to_vec()]);
+        header.set_raw("Accept",     vec![b"application/vnd.github.v3+json".to_vec()]);
+
+        thread_trace!("  Send request");
+        let request      = http_client.request(method, api_call_url).headers(header);
+        let sent         = match body {
+            Some(body) => request.body(Body::BufBody(body.as_bytes(), body.len())).send(),
+            None       => request.send()
+        };
+        let mut response = match sent {
+            Ok(response) => response,
+            Err(err)     => {
+                thread_trace!("Return Err");
+                return Err(format!("Failed to call the API: {}", err))
+            }
+        };
+
+        let mut response_body = String::new();
+        match response.read_to_string(&mut response_body) {
+            Ok(_)    => (),
+            Err(err) => return Err(format!("Failed to read the API response: {}", err))
+        }
+
+        let status = response.status.to_u16();
+        if response_body.trim().is_empty() {
+            thread_trace!("Return Ok({}, Null)", status);
+            return Ok((status, serde_json::Value::Null))
+        }
+
+        match serde_json::from_str(&response_body[..]) {
+            Ok(value) => {
+                thread_trace!("Return Ok({}, value)", status);
+                Ok((status, value))
+            }
+            Err(err)  => Err(format!("Failed to parse the API response ({}): {}", status, err))
+        }
+    }
+}
+
+//Reports the call instead of making it, the reply is a made up {"id": 0, "dry_run": true}
+fn dry_run_request(report: &store::Store, method: Method, endpoint: &str, body: Option<&str>) -> Result<(u16, serde_json::Value), String> {
+
+    thread_info!("Dry run: {} {} {}", method, endpoint, body.unwrap_or(""));
+
+    let mut entry: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+    entry.insert(String::from("at"),       serde_json::Value::I64(UTC::now().timestamp()));
+    entry.insert(String::from("method"),   serde_json::Value::String(method.to_string()));
+    entry.insert(String::from("endpoint"), serde_json::Value::String(String::from(endpoint)));
+    entry.insert(String::from("body"),     serde_json::Value::String(String::from(body.unwrap_or(""))));
+    try!(report.append(DRY_RUN_REPORT_FILE_NAME, &serde_json::Value::Object(entry)));
+
+    let mut reply: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+    reply.insert(String::from("id"),      serde_json::Value::U64(0));
+    reply.insert(String::from("dry_run"), serde_json::Value::Bool(true));
+    let status = match method {
+        Method::Post   => 201,
+        Method::Delete => 204,
+        _              => 200
+    };
+    Ok((status, serde_json::Value::Object(reply)))
+}
+
+fn query_separator(endpoint: &str) -> &'static str {
+    if endpoint.contains('?') {"&"} else {"?"}
+}
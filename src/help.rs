@@ -0,0 +1,210 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+//Help rendered from the command registry: the "help" replies and the
+//command reference of the documentation (docs/commands.rst).
+
+use std::iter;
+
+use commands;
+use permissions;
+use response;
+use response::Message;
+use templates;
+
+include!("logger_macros.rs");
+
+
+////////////////////////////////////////////////////////////
+//                        Replies                         //
+////////////////////////////////////////////////////////////
+
+//Table of the commands the user can run
+pub fn command_table(registry: &commands::CommandRegistry, user_role: permissions::Role, whitelisted: bool, templates: &templates::Templates) -> Message {
+
+    thread_trace!("help.rs: command_table(registry, {:?}, {})", user_role, whitelisted);
+
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    for (name, command) in registry.commands().iter() {
+        if !command.allows(user_role, whitelisted) {
+            continue;
+        }
+        rows.push(vec![
+            response::inline_code(name),
+            command.aliases.iter().map(|alias| response::inline_code(alias)).collect::<Vec<String>>().join(", "),
+            response::inline_code(&command.usage),
+            command.summary.clone()
+        ]);
+    }
+
+    Message::text("Commands you can use:")
+        .table(vec!["Command", "Aliases", "Usage", "Description"], rows)
+        .paragraph(&templates.render("help_footer", &[]))
+}
+
+//Detailed usage of a single command
+pub fn command_usage(name: &str, command: &commands::Command) -> Message {
+
+    thread_trace!("help.rs: command_usage(\"{}\", command)", name);
+
+    let mut message = Message::text(&format!("{}: {}", response::inline_code(name), command.summary))
+        .code_block("", &command.usage);
+
+    if !command.arguments.is_empty() {
+        let rows: Vec<Vec<String>> = command.arguments.iter().map(|argument| vec![
+            response::inline_code(&argument.name),
+            String::from(if argument.required {"Yes"} else {"No"}),
+            argument.description.clone()
+        ]).collect();
+        message = message.table(vec!["Argument", "Required", "Description"], rows);
+    }
+
+    let mut notes: Vec<String> = Vec::new();
+    if !command.aliases.is_empty() {
+        notes.push(format!("Aliases: {}", command.aliases.iter().map(|alias| response::inline_code(alias)).collect::<Vec<String>>().join(", ")));
+    }
+    notes.push(format!("Can be used by: {}", command.permission()));
+    if command.requires_please {
+        notes.push(String::from("Only runs if you say please"));
+    }
+    if let Some(timeout) = command.timeout {
+        notes.push(format!("Runs as a background job, stopped after {} seconds", timeout));
+    }
+    message = message.list(&notes);
+
+    if !command.examples.is_empty() {
+        message = message.paragraph("Examples:").code_block("", &command.examples.join("\n"));
+    }
+    message
+}
+
+
+////////////////////////////////////////////////////////////
+//                      Documentation                     //
+////////////////////////////////////////////////////////////
+
+//Command reference as Markdown
+pub fn markdown(registry: &commands::CommandRegistry) -> String {
+
+    thread_trace!("help.rs: markdown(registry)");
+
+    let mut lines: Vec<String> = Vec::new();
+    lines.push(String::from("# Commands"));
+    lines.push(String::new());
+    lines.push(String::from("Commands are given to the bot by mentioning it at the start of a line of an issue or pull request comment, i.e `@hunter-bot ping`. If the repository has a command prefix, lines can also start with it instead, i.e `/hunter ping`."));
+
+    for (name, command) in registry.commands().iter() {
+        lines.push(String::new());
+        lines.push(format!("## {}", name));
+        lines.push(String::new());
+        lines.push(command.summary.clone());
+        lines.push(String::new());
+        lines.push(String::from("```"));
+        lines.push(command.usage.clone());
+        lines.push(String::from("```"));
+
+        if !command.arguments.is_empty() {
+            lines.push(String::new());
+            for argument in command.arguments.iter() {
+                lines.push(format!("* `{}`{}: {}", argument.name, if argument.required {""} else {" (optional)"}, argument.description));
+            }
+        }
+
+        lines.push(String::new());
+        if !command.aliases.is_empty() {
+            lines.push(format!("Aliases: {}", command.aliases.iter().map(|alias| format!("`{}`", alias)).collect::<Vec<String>>().join(", ")));
+            lines.push(String::new());
+        }
+        lines.push(format!("Can be used by: {}", command.permission()));
+        if command.requires_please {
+            lines.push(String::new());
+            lines.push(String::from("Only runs if you say please."));
+        }
+        if let Some(timeout) = command.timeout {
+            lines.push(String::new());
+            lines.push(format!("Runs as a background job, stopped after {} seconds.", timeout));
+        }
+
+        if !command.examples.is_empty() {
+            lines.push(String::new());
+            lines.push(String::from("Examples:"));
+            lines.push(String::new());
+            lines.push(String::from("```"));
+            for example in command.examples.iter() {
+                lines.push(format!("@hunter-bot {}", example));
+            }
+            lines.push(String::from("```"));
+        }
+    }
+
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+//Command reference as reStructuredText, the format of the Sphinx docs
+pub fn restructured_text(registry: &commands::CommandRegistry) -> String {
+
+    thread_trace!("help.rs: restructured_text(registry)");
+
+    let mut lines: Vec<String> = Vec::new();
+    lines.push(String::from(".. Generated by \"hunter-bot docs --format rst\", do not edit by hand."));
+    lines.push(String::new());
+    lines.push(String::from("Commands"));
+    lines.push(underline("Commands", '-'));
+    lines.push(String::new());
+    lines.push(String::from("Commands are given to the bot by mentioning it at the start of a line of an"));
+    lines.push(String::from("issue or pull request comment, i.e ``@hunter-bot ping``. If the repository has"));
+    lines.push(String::from("a command prefix, lines can also start with it instead, i.e ``/hunter ping``."));
+
+    for (name, command) in registry.commands().iter() {
+        lines.push(String::new());
+        lines.push(name.clone());
+        lines.push(underline(name, '='));
+        lines.push(String::new());
+        lines.push(command.summary.clone());
+        lines.push(String::new());
+        lines.push(String::from(".. code-block:: none"));
+        lines.push(String::new());
+        lines.push(format!("  {}", command.usage));
+
+        if !command.arguments.is_empty() {
+            lines.push(String::new());
+            for argument in command.arguments.iter() {
+                lines.push(format!("* ``{}``{}: {}", argument.name, if argument.required {""} else {" (optional)"}, argument.description));
+            }
+        }
+
+        lines.push(String::new());
+        if !command.aliases.is_empty() {
+            lines.push(format!("Aliases: {}", command.aliases.iter().map(|alias| format!("``{}``", alias)).collect::<Vec<String>>().join(", ")));
+            lines.push(String::new());
+        }
+        lines.push(format!("Can be used by: {}", command.permission()));
+        if command.requires_please {
+            lines.push(String::new());
+            lines.push(String::from("Only runs if you say please."));
+        }
+        if let Some(timeout) = command.timeout {
+            lines.push(String::new());
+            lines.push(format!("Runs as a background job, stopped after {} seconds.", timeout));
+        }
+
+        if !command.examples.is_empty() {
+            lines.push(String::new());
+            lines.push(String::from("Examples:"));
+            lines.push(String::new());
+            lines.push(String::from(".. code-block:: none"));
+            lines.push(String::new());
+            for example in command.examples.iter() {
+                lines.push(format!("  @hunter-bot {}", example));
+            }
+        }
+    }
+
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+fn underline(title: &str, c: char) -> String {
+    iter::repeat(c).take(title.chars().count()).collect()
+}
@@ -0,0 +1,362 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+//Background jobs: commands registered with Command::background() run on a
+//thread of their own. The bot posts a sticky comment when the job starts and
+//edits it as the job reports progress and when it is done. Jobs are stopped
+//on "cancel <job-id>" or after their timeout, the command is expected to
+//check CommandContext::is_cancelled() and return early; whatever it returns
+//after that is dropped.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+
+extern crate chrono;
+use self::chrono::*;
+
+extern crate serde_json;
+
+use commands;
+use config;
+use permissions::Role;
+use response;
+use response::Message;
+use store;
+
+include!("logger_macros.rs");
+
+const JOBS_FILE_NAME: &'static str = "jobs.json";
+
+
+////////////////////////////////////////////////////////////
+//                        JobInfo                         //
+////////////////////////////////////////////////////////////
+
+/// JobInfo
+/// id:           Job id, as given to "cancel"
+/// command_line: Command the job runs
+/// user:         User who started the job
+/// number:       Issue or PR the job reports to
+/// started_at:   Unix timestamp
+/// deadline:     Unix timestamp after which the job is stopped
+/// progress:     Last progress reported by the job
+/// cancelled_by: User who cancelled the job, if any
+#[derive(Clone, Debug)]
+pub struct JobInfo {
+    pub id:           u64,
+    pub command_line: String,
+    pub user:         String,
+    pub number:       u64,
+    pub started_at:   i64,
+    pub deadline:     i64,
+    pub progress:     String,
+    pub cancelled_by: Option<String>,
+    cancelled:        Arc<AtomicBool>
+}
+
+impl JobInfo {
+    //Tag of the sticky comment the job reports to
+    pub fn sticky_tag(&self) -> String {
+        format!("job-{}", self.id)
+    }
+}
+
+#[derive(Debug)]
+struct JobsState {
+    jobs:    BTreeMap<u64, JobInfo>,
+    next_id: u64
+}
+
+
+////////////////////////////////////////////////////////////
+//                      JobManager                        //
+////////////////////////////////////////////////////////////
+
+///Running jobs, clones share the same jobs.
+///Job ids keep increasing across restarts so that the sticky comment of an
+///old job is never edited by a new one.
+#[derive(Clone, Debug)]
+pub struct JobManager {
+    store: store::Store,
+    state: Arc<Mutex<JobsState>>
+}
+
+impl JobManager {
+
+    pub fn new(tsconfig: &Arc<Mutex<config::ConfigHandler>>) -> JobManager {
+
+        thread_trace!("jobs.rs: JobManager::new(tsconfig)");
+
+        let store   = store::Store::new(tsconfig);
+        let next_id = match store.load(JOBS_FILE_NAME) {
+            Ok(Some(value)) => value.find("next_id").and_then(|next_id| next_id.as_u64()).unwrap_or(1),
+            Ok(None)        => 1,
+            Err(err)        => {
+                thread_error!("Failed to load the job ids, starting over: {}", err);
+                1
+            }
+        };
+
+        JobManager {
+            store: store,
+            state: Arc::new(Mutex::new(JobsState {
+                jobs:    BTreeMap::new(),
+                next_id: next_id
+            }))
+        }
+    }
+
+    //Runs the command on a thread of its own, returns the job id.
+    //The "started" comment is posted before the job can report progress.
+    pub fn start(&self, command_line: &str, timeout: u64, handler: Arc<commands::BotCommand>, mut context: commands::CommandContext) -> u64 {
+
+        thread_trace!("jobs.rs: JobManager::start(&self, \"{}\", {}, handler, context)", command_line, timeout);
+
+        let now = UTC::now().timestamp();
+        let job = {
+            let mut state  = self.state.lock().unwrap();
+            let     job    = JobInfo {
+                id:           state.next_id,
+                command_line: String::from(command_line),
+                user:         context.event.user.clone(),
+                number:       context.event.number,
+                started_at:   now,
+                deadline:     now + timeout as i64,
+                progress:     String::new(),
+                cancelled_by: None,
+                cancelled:    Arc::new(AtomicBool::new(false))
+            };
+            state.next_id += 1;
+            state.jobs.insert(job.id, job.clone());
+            self.save_next_id(state.next_id);
+            job
+        };
+        thread_info!("Job {} started by {}: {}", job.id, job.user, command_line);
+
+        context.replies.send(Message::text(&format!("Job {} started: {}", response::inline_code(&job.id.to_string()), response::inline_code(command_line)))
+            .paragraph(&format!("Say {} to stop it.", response::inline_code(&format!("cancel {}", job.id))))
+            .sticky(&job.sticky_tag()));
+
+        context.job = Some(JobHandle {
+            id:        job.id,
+            manager:   self.clone(),
+            cancelled: job.cancelled.clone()
+        });
+
+        let id      = job.id;
+        let manager = self.clone();
+        let spawned = thread::Builder::new().name(format!("job-{}", id)).spawn(move || {
+            manager.supervise(job, handler, context);
+        });
+        if let Err(err) = spawned {
+            thread_error!("Failed to start the job thread: {}", err);
+            self.finish(id);
+        }
+        id
+    }
+
+    //Stops a job on behalf of `by`. Err if there is no such job.
+    pub fn cancel(&self, id: u64, by: &str) -> Result<JobInfo, String> {
+
+        thread_trace!("jobs.rs: JobManager::cancel(&self, {}, \"{}\")", id, by);
+
+        let mut state = self.state.lock().unwrap();
+        match state.jobs.get_mut(&id) {
+            Some(job) => {
+                job.cancelled.store(true, Ordering::SeqCst);
+                job.cancelled_by = Some(String::from(by));
+                thread_info!("Job {} cancelled by {}", id, by);
+                Ok(job.clone())
+            }
+            None      => Err(format!("There is no running job {}.", response::inline_code(&id.to_string())))
+        }
+    }
+
+    pub fn get(&self, id: u64) -> Option<JobInfo> {
+        self.state.lock().unwrap().jobs.get(&id).cloned()
+    }
+
+    //Running jobs, oldest first
+    pub fn running(&self) -> Vec<JobInfo> {
+        self.state.lock().unwrap().jobs.values().cloned().collect()
+    }
+
+    fn set_progress(&self, id: u64, progress: &str) {
+        if let Some(job) = self.state.lock().unwrap().jobs.get_mut(&id) {
+            job.progress = String::from(progress);
+        }
+    }
+
+    fn finish(&self, id: u64) -> Option<JobInfo> {
+        self.state.lock().unwrap().jobs.remove(&id)
+    }
+
+    fn save_next_id(&self, next_id: u64) {
+        let mut object: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+        object.insert(String::from("next_id"), serde_json::Value::U64(next_id));
+        if let Err(err) = self.store.save(JOBS_FILE_NAME, &serde_json::Value::Object(object)) {
+            thread_error!("Failed to save the job ids: {}", err);
+        }
+    }
+
+    //Runs the command on a worker thread and waits for it, its result or the
+    //reason it was stopped goes to the sticky comment of the job
+    fn supervise(&self, job: JobInfo, handler: Arc<commands::BotCommand>, mut context: commands::CommandContext) {
+
+        thread_trace!("jobs.rs: JobManager::supervise(&self, job, handler, context)");
+
+        let replies  = context.replies.clone();
+        let (tx, rx) = channel();
+        let worker   = thread::Builder::new().name(format!("job-{}-worker", job.id)).spawn(move || {
+            let result = handler.run(&mut context);
+            let _      = tx.send(result);
+        });
+        if let Err(err) = worker {
+            thread_error!("Failed to start the job worker: {}", err);
+            self.finish(job.id);
+            return;
+        }
+
+        let reply = self.wait_for_result(&job, rx);
+
+        self.finish(job.id);
+        thread_info!("Job {} is done", job.id);
+        replies.send(reply.sticky(&job.sticky_tag()));
+    }
+
+    //Final report of the job: its result, or why it was stopped
+    fn wait_for_result(&self, job: &JobInfo, rx: Receiver<Result<Message, String>>) -> Message {
+
+        let header = |status: &str| format!("Job {} {}: {}", response::inline_code(&job.id.to_string()), status, response::inline_code(&job.command_line));
+
+        loop {
+            match rx.recv_timeout(time::Duration::from_millis(200)) {
+                Ok(Ok(msg))                         => return Message::text(&header(&format!("finished in {}s", UTC::now().timestamp() - job.started_at))).append(msg),
+                Ok(Err(err))                        => return Message::text(&header("failed")).paragraph(&format!("An error occurred while executing the command: {}", err)),
+                Err(RecvTimeoutError::Disconnected) => return Message::text(&header("failed")).paragraph("The job stopped unexpectedly."),
+                Err(RecvTimeoutError::Timeout)      => ()
+            }
+
+            if job.cancelled.load(Ordering::SeqCst) {
+                let by = self.get(job.id).and_then(|job| job.cancelled_by).unwrap_or(String::new());
+                return Message::text(&header("was cancelled")).paragraph(&format!("Cancelled by @{}.", by))
+            }
+            if UTC::now().timestamp() >= job.deadline {
+                job.cancelled.store(true, Ordering::SeqCst);
+                thread_warn!("Job {} timed out", job.id);
+                return Message::text(&header("timed out")).paragraph(&format!("It did not finish within {} seconds.", job.deadline - job.started_at))
+            }
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                       JobHandle                        //
+////////////////////////////////////////////////////////////
+
+///What a command running as a job gets in CommandContext::job
+#[derive(Clone, Debug)]
+pub struct JobHandle {
+    pub id:    u64,
+    manager:   JobManager,
+    cancelled: Arc<AtomicBool>
+}
+
+impl JobHandle {
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    //Edits the sticky comment of the job
+    pub fn progress(&self, replies: &commands::ReplySink, progress: &str) {
+        if self.is_cancelled() {
+            return;
+        }
+        self.manager.set_progress(self.id, progress);
+        if let Some(job) = self.manager.get(self.id) {
+            replies.send(Message::text(&format!("Job {} is running: {}", response::inline_code(&job.id.to_string()), response::inline_code(&job.command_line)))
+                .paragraph(progress)
+                .paragraph(&format!("Say {} to stop it.", response::inline_code(&format!("cancel {}", job.id))))
+                .sticky(&job.sticky_tag()));
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                        Commands                        //
+////////////////////////////////////////////////////////////
+
+///"cancel <job-id>", jobs can be cancelled by whoever started them or by whitelisted users
+pub struct CancelCommand;
+
+impl CancelCommand {
+    pub fn command() -> commands::Command {
+        commands::Command::new(false, Role::Anyone, CancelCommand)
+            .summary("Stops a background job.")
+            .usage("cancel <job-id>")
+            .argument("job-id", true, "Id of the job, as given when it started or by the jobs command.")
+            .example("cancel 12")
+    }
+}
+
+impl commands::BotCommand for CancelCommand {
+    fn run(&self, context: &mut commands::CommandContext) -> Result<Message, String> {
+
+        thread_trace!("jobs.rs: CancelCommand::run(&self, context)");
+
+        let id: u64 = match context.args.positional(0) {
+            Some(id) => try!(id.trim_left_matches('#').parse().map_err(|_| format!("{} is not a valid job id.", response::inline_code(id)))),
+            None     => return Err(format!("Usage: {}", response::inline_code("cancel <job-id>")))
+        };
+
+        let job = try!(context.jobs.get(id).ok_or(format!("There is no running job {}.", response::inline_code(&id.to_string()))));
+        if job.user != context.event.user && !context.permissions.is_whitelisted(&context.event) {
+            return Err(format!("Only @{} or whitelisted users can cancel job {}.", job.user, response::inline_code(&id.to_string())))
+        }
+
+        try!(context.jobs.cancel(id, &context.event.user));
+        Ok(Message::text(&format!("Cancelling job {}: {}", response::inline_code(&id.to_string()), response::inline_code(&job.command_line))))
+    }
+}
+
+///"jobs" lists the running jobs
+pub struct JobsCommand;
+
+impl JobsCommand {
+    pub fn command() -> commands::Command {
+        commands::Command::new(false, Role::Anyone, JobsCommand)
+            .summary("Lists the background jobs that are running.")
+            .usage("jobs")
+            .example("jobs")
+    }
+}
+
+impl commands::BotCommand for JobsCommand {
+    fn run(&self, context: &mut commands::CommandContext) -> Result<Message, String> {
+
+        thread_trace!("jobs.rs: JobsCommand::run(&self, context)");
+
+        let jobs = context.jobs.running();
+        if jobs.is_empty() {
+            return Ok(Message::text("No jobs are running."))
+        }
+
+        let now                    = UTC::now().timestamp();
+        let rows: Vec<Vec<String>> = jobs.iter().map(|job| vec![
+            job.id.to_string(),
+            response::inline_code(&job.command_line),
+            job.user.clone(),
+            format!("#{}", job.number),
+            format!("{}s", now - job.started_at),
+            job.progress.clone()
+        ]).collect();
+
+        Ok(Message::text(&format!("{} job(s) running:", jobs.len()))
+            .table(vec!["Id", "Command", "Started by", "Issue", "Running for", "Progress"], rows))
+    }
+}
@@ -3,12 +3,17 @@
 
 use std::error::Error;
 use std::fs;
-use std::fs::OpenOptions;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+extern crate chrono;
+use chrono::TimeZone;
 
 extern crate clap;
-use clap::{Arg, App};
+use clap::{Arg, App, ArgMatches, SubCommand};
 
 #[macro_use]
 extern crate log;
@@ -16,10 +21,35 @@ use log::LogLevelFilter;
 
 extern crate hyper;
 
+mod archive;
+mod args;
+mod audit;
+mod automerge;
 mod commands;
 mod config;
+mod github;
+mod help;
+mod jobs;
 mod logger;
+mod mentions;
+mod merge;
+mod moderation;
+mod outbound;
+mod permissions;
+mod queue;
+mod reactions;
+mod response;
+mod status;
+mod sticky;
+mod store;
+mod templates;
 mod webhooks;
+mod whitelist;
+
+#[cfg(test)]
+mod test_support;
+#[cfg(test)]
+mod tests;
 
 include!("logger_macros.rs");
 
@@ -113,6 +143,66 @@ fn log_size_validator(log_size: String) -> Result<(), String> {
 }
 
 
+//Subcommands
+fn dump_docs(matches: &ArgMatches) {
+
+    let registry = commands::CommandRegistry::builtin();
+    let docs     = match matches.value_of("FORMAT").unwrap_or("rst") {
+        "markdown" => help::markdown(&registry),
+        _          => help::restructured_text(&registry)
+    };
+
+    match matches.value_of("OUTPUT") {
+        Some(path) => {
+            match File::create(path).and_then(|mut file| file.write_all(docs.as_bytes())) {
+                Ok(())   => (),
+                Err(err) => {panic!("Failed to write \"{}\": {}", path, err.description());}
+            }
+        }
+        None       => {print!("{}", docs);}
+    }
+}
+
+fn dump_audit(matches: &ArgMatches, config_path: &str) {
+
+    let mut config = config::ConfigHandler::new();
+    match config.load(&config_path.to_string()) {
+        Ok(())   => (),
+        Err(err) => {panic!("Error loading the config: {}", err);}
+    }
+    let tsconfig = Arc::new(Mutex::new(config));
+
+    let since = match matches.value_of("SINCE") {
+        Some(since) => match audit::parse_since(since, chrono::UTC::now().timestamp()) {
+            Ok(since) => Some(since),
+            Err(err)  => {panic!("{}", err);}
+        },
+        None        => None
+    };
+    let entries = match audit::entries(&tsconfig, matches.value_of("USER"), since) {
+        Ok(entries) => entries,
+        Err(err)    => {panic!("{}", err);}
+    };
+
+    let output = if matches.is_present("CSV") {
+        audit::to_csv(&entries)
+    } else {
+        entries.iter().map(|entry| format!("{}  {:<20} {:<12} #{:<6} {:<10} {:>7} ms  {}\n",
+            chrono::UTC.timestamp(entry.at, 0).format("%Y-%m-%d %H:%M:%S"), entry.user, entry.role, entry.number, entry.status, entry.duration_ms, entry.command)).collect()
+    };
+
+    match matches.value_of("OUTPUT") {
+        Some(path) => {
+            match File::create(path).and_then(|mut file| file.write_all(output.as_bytes())) {
+                Ok(())   => (),
+                Err(err) => {panic!("Failed to write \"{}\": {}", path, err.description());}
+            }
+        }
+        None       => {print!("{}", output);}
+    }
+}
+
+
 ////////////////////////////////////////////////////////////
 //                          Main                          //
 ////////////////////////////////////////////////////////////
@@ -146,9 +236,54 @@ fn main() {
         .help("Sets the maximum log file (in MB) before being rotated.")
         .validator(log_size_validator)
         .takes_value(true))
+    .arg(Arg::with_name("DRYRUN")
+        .long("dry-run")
+        .help("Logs the changes the bot would make on Github (comments, labels, merges, ...) and writes them to \"data_dir\"/dry-run.json instead of making them."))
+    .subcommand(SubCommand::with_name("docs")
+        .about("Prints the command reference generated from the registered commands.")
+        .arg(Arg::with_name("FORMAT")
+            .short("f")
+            .long("format")
+            .help("Output format, \"rst\" (the default, as in docs/commands.rst) or \"markdown\".")
+            .possible_values(&["rst", "markdown"])
+            .takes_value(true))
+        .arg(Arg::with_name("OUTPUT")
+            .short("o")
+            .long("output")
+            .help("Writes the reference to this file instead of the standard output.")
+            .takes_value(true)))
+    .subcommand(SubCommand::with_name("audit")
+        .about("Prints the commands given to the bot, from the audit log of the \"data_dir\" of the config.")
+        .arg(Arg::with_name("USER")
+            .help("Only prints the commands of this Github user.")
+            .index(1))
+        .arg(Arg::with_name("SINCE")
+            .long("since")
+            .help("Only prints the commands given after a date (2016-11-30) or within an age (30m, 12h, 7d).")
+            .takes_value(true))
+        .arg(Arg::with_name("CSV")
+            .long("csv")
+            .help("Prints CSV instead of a table."))
+        .arg(Arg::with_name("OUTPUT")
+            .short("o")
+            .long("output")
+            .help("Writes to this file instead of the standard output.")
+            .takes_value(true)))
     .get_matches();
 
+    //Does not need the config nor the logger
+    if let Some(docs_matches) = matches.subcommand_matches("docs") {
+        dump_docs(docs_matches);
+        return;
+    }
+
     let hunterbot_config_path = matches.value_of("CONFIG").unwrap_or("./HunterBotConfig.toml");
+
+    //Only needs the config
+    if let Some(audit_matches) = matches.subcommand_matches("audit") {
+        dump_audit(audit_matches, hunterbot_config_path);
+        return;
+    }
     let hunterbot_log_dir     = matches.value_of("LOG").unwrap_or("./");
     let log_size              = u64::from_str(matches.value_of("LOGSIZE").unwrap_or("5")).unwrap();
     let max_log_level         = match matches.value_of("MAXLOGLEVEL").unwrap_or("info") {
@@ -175,6 +310,7 @@ fn main() {
     thread_debug!("Option: hunterbot_log_dir:     {}", hunterbot_log_dir);
     thread_debug!("Option: log_size:              {}", log_size);
     thread_debug!("Option: max_log_level:         {}", max_log_level);
+    thread_debug!("Option: dry_run:               {}", matches.is_present("DRYRUN"));
 
     //Load config
     let mut config = config::ConfigHandler::new();
@@ -185,7 +321,12 @@ fn main() {
         Err(err) => {thread_crash!("Error loading the config: {}", err);}
     }
 
+    config.dry_run = matches.is_present("DRYRUN");
+    config.log_dir = PathBuf::from(hunterbot_log_dir);
     config.validate();
+    if config.is_dry_run() {
+        thread_info!("Dry run, changes to Github are written to {} instead of being made.", config.get_data_file_path("dry-run.json").display());
+    }
 
     //Setup webhooks
     webhooks::register(&mut config);
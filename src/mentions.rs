@@ -0,0 +1,120 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+//Finds the commands addressed to the bot in a comment. A command is a line
+//starting with a mention of the bot or with the command prefix of the
+//repository (i.e `/hunter test Boost`), lines inside code blocks, block
+//quotes and HTML comments are ignored so that quoting the bot or showing an
+//example does not trigger anything.
+
+extern crate regex;
+use self::regex::Regex;
+
+use config;
+
+include!("logger_macros.rs");
+
+
+////////////////////////////////////////////////////////////
+//                          Funcs                         //
+////////////////////////////////////////////////////////////
+
+///"@bot label needs-review\n> @bot merge\n@bot test Boost" gives ["label needs-review", "test Boost"].
+///A bare mention gives a single empty command so that the bot can answer it.
+///prefix: Also starts a command, i.e "/hunter" or "!hb", None for mentions only
+pub fn extract_commands(comment_body: &str, bot_name: &str, prefix: Option<&str>) -> Vec<String> {
+
+    thread_trace!("mentions.rs: extract_commands(comment_body, \"{}\", {:?})", bot_name, prefix);
+
+    //Up to 3 spaces of indentation, more is an indented code block
+    let mut triggers = vec![Regex::new(&format!(r"^(?i)[ ]{{0,3}}@{}", regex::quote(bot_name))[..]).unwrap()];
+    if let Some(prefix) = prefix {
+        triggers.push(Regex::new(&format!(r"^(?i)[ ]{{0,3}}{}", regex::quote(prefix))[..]).unwrap());
+    }
+
+    let mut commands:     Vec<String>  = Vec::new();
+    let mut fence:        Option<char> = None;
+    let mut fence_length: usize        = 0;
+    let mut in_comment:   bool         = false;
+
+    for line in comment_body.lines() {
+
+        let trimmed = line.trim_left();
+
+        //Fenced code blocks, closed by a fence at least as long as the opening one
+        if let Some(fence_char) = fence {
+            let length = trimmed.chars().take_while(|c| *c == fence_char).count();
+            if length >= fence_length && trimmed.trim_right().chars().all(|c| c == fence_char) {
+                fence = None;
+            }
+            continue;
+        }
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            let fence_char = trimmed.chars().next().unwrap();
+            fence_length   = trimmed.chars().take_while(|c| *c == fence_char).count();
+            fence          = Some(fence_char);
+            continue;
+        }
+
+        //HTML comments
+        if in_comment {
+            if line.contains("-->") {
+                in_comment = false;
+            }
+            continue;
+        }
+        if trimmed.starts_with("<!--") {
+            in_comment = !trimmed.contains("-->");
+            continue;
+        }
+
+        //Block quotes and indented code blocks
+        if trimmed.starts_with('>') || line.starts_with("    ") || line.starts_with('\t') {
+            continue;
+        }
+
+        let end = match triggers.iter().filter_map(|trigger| trigger.find(line)).next() {
+            Some((_, end)) => end,
+            None           => continue
+        };
+
+        //A mention or a prefix ending with a name has to end there, "@bot2"
+        //or "@bot-dev" are other users and "/hunterbot" is another prefix
+        let rest = &line[end..];
+        if line[..end].chars().last().map(is_name_char).unwrap_or(false) && rest.chars().next().map(is_name_char).unwrap_or(false) {
+            continue;
+        }
+
+        let command = rest.trim_left_matches(|c: char| c == ':' || c == ',').trim();
+        commands.push(String::from(command));
+    }
+
+    //A bare mention is only kept if it is all there is
+    if commands.iter().any(|command| !command.is_empty()) {
+        commands.retain(|command| !command.is_empty());
+    } else {
+        commands.truncate(1);
+    }
+
+    thread_trace!("Return {:?}", commands);
+    commands
+}
+
+//The prefix of the repository ("[prefixes]" section, i.e `"ruslo/hunter" = "/hunter"`),
+//otherwise the one of every repository ("command_prefix"), None if there is none
+pub fn command_prefix(config: &mut config::ConfigHandler, repo: &str) -> Option<String> {
+    let prefix = match config.get_string("prefixes", repo) {
+        Ok(prefix) => prefix,
+        Err(_)     => config.get_string_or("config", "command_prefix", "")
+    };
+    let prefix = prefix.trim();
+    if prefix.is_empty() {
+        None
+    } else {
+        Some(String::from(prefix))
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_'
+}
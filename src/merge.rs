@@ -0,0 +1,396 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+//"merge": merges a pull request once it is approved, green and mergeable,
+//otherwise replies with what is in the way. The checks are shared with the
+//commands that merge on their own.
+
+use std::collections::BTreeMap;
+
+extern crate hyper;
+use self::hyper::method::Method;
+
+extern crate serde_json;
+
+use commands;
+use config;
+use github;
+use permissions::Role;
+use response;
+use response::Message;
+
+include!("logger_macros.rs");
+
+pub const MERGE_METHODS: &'static [&'static str] = &["merge", "squash", "rebase"];
+
+//Reviews of users with these associations to the repository count, drive-by reviews do not
+const REVIEWER_ASSOCIATIONS: &'static [&'static str] = &["OWNER", "MEMBER", "COLLABORATOR"];
+
+//Conclusions of check runs that do not block a merge
+const PASSING_CONCLUSIONS: &'static [&'static str] = &["success", "neutral", "skipped"];
+
+
+////////////////////////////////////////////////////////////
+//                      PullRequest                       //
+////////////////////////////////////////////////////////////
+
+/// PullRequest
+/// number:    PR number
+/// title:     Title of the PR
+/// body:      Description of the PR, empty if none
+/// author:    User who opened the PR
+/// state:     "open" or "closed"
+/// merged:    Whether the PR was merged already
+/// draft:     Whether the PR is a draft
+/// mergeable: Whether Github can merge it, None while Github is still working it out
+/// head_sha:  Last commit of the PR
+/// base_ref:  Branch the PR goes into, i.e "master"
+#[derive(Clone, Debug, PartialEq)]
+pub struct PullRequest {
+    pub number:    u64,
+    pub title:     String,
+    pub body:      String,
+    pub author:    String,
+    pub state:     String,
+    pub merged:    bool,
+    pub draft:     bool,
+    pub mergeable: Option<bool>,
+    pub head_sha:  String,
+    pub base_ref:  String
+}
+
+impl PullRequest {
+
+    //Err if the issue is not a pull request
+    pub fn fetch(client: &github::GithubClient, repo: &str, number: u64) -> Result<PullRequest, String> {
+
+        thread_trace!("merge.rs: PullRequest::fetch(client, \"{}\", {})", repo, number);
+
+        let endpoint        = format!("repos/{}/pulls/{}", repo, number);
+        let (status, value) = try!(client.request(Method::Get, &endpoint, None));
+        match status {
+            200 => PullRequest::from_json(&value),
+            404 => Err(format!("#{} is not a pull request.", number)),
+            _   => Err(format!("GET {} failed with status {}", endpoint, status))
+        }
+    }
+
+    pub fn from_json(value: &serde_json::Value) -> Result<PullRequest, String> {
+        let string = |path: &[&str]| value.find_path(path).and_then(|field| field.as_str()).unwrap_or("").to_string();
+        Ok(PullRequest {
+            number:    try!(value.find("number").and_then(|number| number.as_u64()).ok_or(String::from("The pull request has no number."))),
+            title:     string(&["title"]),
+            body:      string(&["body"]),
+            author:    string(&["user", "login"]),
+            state:     string(&["state"]),
+            merged:    value.find("merged").and_then(|merged| merged.as_bool()).unwrap_or(false),
+            draft:     value.find("draft").and_then(|draft| draft.as_bool()).unwrap_or(false),
+            mergeable: value.find("mergeable").and_then(|mergeable| mergeable.as_bool()),
+            head_sha:  try!(value.find_path(&["head", "sha"]).and_then(|sha| sha.as_str()).map(String::from).ok_or(String::from("The pull request has no head commit."))),
+            base_ref:  string(&["base", "ref"])
+        })
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                       Readiness                        //
+////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheckState {
+    Passed,
+    //Could still pass without anyone doing anything, i.e CI is running
+    Pending,
+    Failed
+}
+
+/// Check
+/// state: How the condition stands
+/// text:  Condition as shown in the checklist, i.e "CI is green"
+#[derive(Clone, Debug, PartialEq)]
+pub struct Check {
+    pub state: CheckState,
+    pub text:  String
+}
+
+impl Check {
+    fn new(state: CheckState, text: &str) -> Check {
+        Check {
+            state: state,
+            text:  String::from(text)
+        }
+    }
+}
+
+/// Readiness
+/// pull:      The PR the checks were made on
+/// checks:    Every condition of a merge, in the order of the checklist
+/// approvers: Users whose last review approves the PR
+#[derive(Clone, Debug)]
+pub struct Readiness {
+    pub pull:      PullRequest,
+    pub checks:    Vec<Check>,
+    pub approvers: Vec<String>
+}
+
+impl Readiness {
+
+    pub fn is_ready(&self) -> bool {
+        self.checks.iter().all(|check| check.state == CheckState::Passed)
+    }
+
+    //Checks that failed, they need someone to act before a merge
+    pub fn failures(&self) -> Vec<&Check> {
+        self.checks.iter().filter(|check| check.state == CheckState::Failed).collect()
+    }
+
+    //"[x] Approved by 1 reviewer(s)", one item per check
+    pub fn checklist(&self) -> Vec<String> {
+        self.checks.iter().map(|check| {
+            match check.state {
+                CheckState::Passed  => format!("[x] {}", check.text),
+                CheckState::Pending => format!("[ ] {} (pending)", check.text),
+                CheckState::Failed  => format!("[ ] {}", check.text)
+            }
+        }).collect()
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                      MergeCommand                      //
+////////////////////////////////////////////////////////////
+
+///"merge [merge|squash|rebase]"
+pub struct MergeCommand;
+
+impl MergeCommand {
+    pub fn command() -> commands::Command {
+        commands::Command::new(false, Role::Write, MergeCommand)
+            .summary("Merges the pull request if it is approved, green and mergeable, otherwise lists what is blocking it.")
+            .usage("merge [merge|squash|rebase]")
+            .argument("merge|squash|rebase", false, "How to merge, \"merge_method\" of the config or \"merge\" by default.")
+            .example("merge")
+            .example("merge squash")
+    }
+}
+
+impl commands::BotCommand for MergeCommand {
+    fn run(&self, context: &mut commands::CommandContext) -> Result<Message, String> {
+
+        thread_trace!("merge.rs: MergeCommand::run(&self, context)");
+
+        let method = match context.args.positional(0) {
+            Some(method) => String::from(method),
+            None         => context.config.get_string_or("config", "merge_method", "merge")
+        };
+        if !MERGE_METHODS.iter().any(|known| *known == &method[..]) {
+            return Err(format!("{} is not a merge method, use one of {}.", response::inline_code(&method),
+                MERGE_METHODS.iter().map(|method| response::inline_code(method)).collect::<Vec<String>>().join(", ")))
+        }
+
+        let repo      = context.config.get_string_required("config", "github_follow_repo");
+        let readiness = try!(readiness(&context.github, &mut context.config, &repo, context.event.number));
+        if !readiness.is_ready() {
+            return Ok(Message::text(&format!("#{} cannot be merged yet:", context.event.number))
+                .list(&readiness.checklist()))
+        }
+
+        let sha = try!(merge(&context.github, &repo, &readiness, &method, &context.event.user));
+        Ok(Message::text(&format!("Merged #{} into {} with {} ({}).", context.event.number,
+            response::inline_code(&readiness.pull.base_ref), response::inline_code(&method), sha)))
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                          Funcs                         //
+////////////////////////////////////////////////////////////
+
+//Everything a merge needs: an open non-draft PR, enough approvals and no
+//changes requested, green statuses and check runs and no conflicts.
+//"required_approvals" of the config, 1 by default.
+pub fn readiness(client: &github::GithubClient, config: &mut config::ConfigHandler, repo: &str, number: u64) -> Result<Readiness, String> {
+
+    thread_trace!("merge.rs: readiness(client, config, \"{}\", {})", repo, number);
+
+    let required = try!(config.get_string_or("config", "required_approvals", "1").parse::<usize>()
+        .map_err(|err| format!("\"required_approvals\" of the config is not a number: {}", err)));
+    let pull     = try!(PullRequest::fetch(client, repo, number));
+
+    let mut checks: Vec<Check> = Vec::new();
+    if pull.merged {
+        checks.push(Check::new(CheckState::Failed, "The pull request is not merged yet"));
+    } else if pull.state != "open" {
+        checks.push(Check::new(CheckState::Failed, "The pull request is open"));
+    } else {
+        checks.push(Check::new(CheckState::Passed, "The pull request is open"));
+    }
+    checks.push(Check::new(if pull.draft {CheckState::Failed} else {CheckState::Passed}, "The pull request is not a draft"));
+
+    let (approvers, changes_requested) = try!(reviews(client, repo, number));
+    let approvals_state = if approvers.len() >= required {CheckState::Passed} else {CheckState::Pending};
+    checks.push(Check::new(approvals_state, &format!("Approved by {} reviewer(s), {} needed", approvers.len(), required)));
+    if changes_requested.is_empty() {
+        checks.push(Check::new(CheckState::Passed, "No changes requested"));
+    } else {
+        let users: Vec<String> = changes_requested.iter().map(|user| format!("@{}", user)).collect();
+        checks.push(Check::new(CheckState::Failed, &format!("No changes requested, requested by {}", users.join(", "))));
+    }
+
+    checks.extend(try!(ci(client, repo, &pull.head_sha)));
+
+    checks.push(match pull.mergeable {
+        Some(true)  => Check::new(CheckState::Passed,  "No conflicts with the base branch"),
+        Some(false) => Check::new(CheckState::Failed,  "No conflicts with the base branch"),
+        None        => Check::new(CheckState::Pending, "No conflicts with the base branch, Github is still checking")
+    });
+
+    thread_debug!("Readiness of #{}: {:?}", number, checks);
+    Ok(Readiness {
+        pull:      pull,
+        checks:    checks,
+        approvers: approvers
+    })
+}
+
+//Merges the commit the checks were made on, Github refuses if the PR moved since.
+//Ok: SHA of the merge commit
+pub fn merge(client: &github::GithubClient, repo: &str, readiness: &Readiness, method: &str, merged_by: &str) -> Result<String, String> {
+
+    thread_trace!("merge.rs: merge(client, \"{}\", readiness, \"{}\", \"{}\")", repo, method, merged_by);
+
+    let pull = &readiness.pull;
+    let mut body: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+    body.insert(String::from("sha"),          serde_json::Value::String(pull.head_sha.clone()));
+    body.insert(String::from("merge_method"), serde_json::Value::String(String::from(method)));
+    if method != "rebase" {
+        body.insert(String::from("commit_title"),   serde_json::Value::String(commit_title(pull, method)));
+        body.insert(String::from("commit_message"), serde_json::Value::String(commit_message(readiness, merged_by)));
+    }
+    let body = try!(serde_json::to_string(&serde_json::Value::Object(body)).map_err(|err| format!("Failed to serialize the merge request: {}", err)));
+
+    let endpoint        = format!("repos/{}/pulls/{}/merge", repo, pull.number);
+    let (status, value) = try!(client.request(Method::Put, &endpoint, Some(&body)));
+    let message         = value.find("message").and_then(|message| message.as_str()).unwrap_or("").to_string();
+    match status {
+        200 => Ok(value.find("sha").and_then(|sha| sha.as_str()).unwrap_or("").to_string()),
+        405 => Err(format!("Github refused to merge #{}: {}", pull.number, message)),
+        409 => Err(format!("#{} changed while it was being merged, nothing was merged.", pull.number)),
+        _   => Err(format!("PUT {} failed with status {}: {}", endpoint, status, message))
+    }
+}
+
+//"Add Boost 1.62 (#12)"
+fn commit_title(pull: &PullRequest, method: &str) -> String {
+    if method == "merge" {
+        format!("Merge #{}: {}", pull.number, pull.title)
+    } else {
+        format!("{} (#{})", pull.title, pull.number)
+    }
+}
+
+//Description of the PR followed by who approved and merged it
+fn commit_message(readiness: &Readiness, merged_by: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let description = readiness.pull.body.replace("\r\n", "\n");
+    if !description.trim().is_empty() {
+        lines.push(String::from(description.trim()));
+        lines.push(String::new());
+    }
+    if !readiness.approvers.is_empty() {
+        lines.push(format!("Approved-by: {}", readiness.approvers.join(", ")));
+    }
+    lines.push(format!("Merged-by: {}", merged_by));
+    lines.join("\n")
+}
+
+//The last approving or blocking review of each owner, member or collaborator
+//counts, comments do not.
+//Ok: (users approving, users requesting changes)
+fn reviews(client: &github::GithubClient, repo: &str, number: u64) -> Result<(Vec<String>, Vec<String>), String> {
+
+    let mut last_reviews: BTreeMap<String, String> = BTreeMap::new();
+    for review in try!(client.get_all(&format!("repos/{}/pulls/{}/reviews", repo, number))) {
+        let user        = review.find_path(&["user", "login"]).and_then(|login| login.as_str()).unwrap_or("");
+        let state       = review.find("state").and_then(|state| state.as_str()).unwrap_or("");
+        let association = review.find("author_association").and_then(|association| association.as_str()).unwrap_or("NONE");
+        if !REVIEWER_ASSOCIATIONS.iter().any(|known| *known == association) {
+            thread_debug!("Ignoring the review of {}, {} of the repository", user, association);
+            continue;
+        }
+        match state {
+            "APPROVED" | "CHANGES_REQUESTED" | "DISMISSED" => {last_reviews.insert(String::from(user), String::from(state));}
+            _                                              => ()
+        }
+    }
+
+    let approvers         = last_reviews.iter().filter(|&(_, state)| state == "APPROVED").map(|(user, _)| user.clone()).collect();
+    let changes_requested = last_reviews.iter().filter(|&(_, state)| state == "CHANGES_REQUESTED").map(|(user, _)| user.clone()).collect();
+    Ok((approvers, changes_requested))
+}
+
+//Commit statuses and check runs of a commit. Right after a push CI did not
+//report anything yet, a commit without any is pending rather than green.
+pub fn ci(client: &github::GithubClient, repo: &str, sha: &str) -> Result<Vec<Check>, String> {
+    let statuses = try!(combined_status(client, repo, sha));
+    let runs     = try!(check_runs(client, repo, sha));
+    let state    = if statuses.is_none() && runs.is_none() {CheckState::Pending} else {CheckState::Passed};
+    Ok(vec![
+        statuses.unwrap_or(Check::new(state, "Commit statuses are green, none reported")),
+        runs.unwrap_or(Check::new(state, "Check runs are green, none reported"))
+    ])
+}
+
+//Commit statuses of the commit, i.e Travis and AppVeyor
+//Ok: None if there are none
+fn combined_status(client: &github::GithubClient, repo: &str, sha: &str) -> Result<Option<Check>, String> {
+
+    let status = try!(client.get(&format!("repos/{}/commits/{}/status", repo, sha)));
+    let count  = status.find("statuses").and_then(|statuses| statuses.as_array()).map(|statuses| statuses.len()).unwrap_or(0);
+    if count == 0 {
+        return Ok(None)
+    }
+
+    let failing: Vec<String> = status.find("statuses").and_then(|statuses| statuses.as_array()).unwrap().iter()
+        .filter(|status| status.find("state").and_then(|state| state.as_str()).map(|state| state == "failure" || state == "error").unwrap_or(false))
+        .filter_map(|status| status.find("context").and_then(|context| context.as_str()).map(response::inline_code))
+        .collect();
+
+    Ok(Some(match status.find("state").and_then(|state| state.as_str()).unwrap_or("") {
+        "success" => Check::new(CheckState::Passed, "Commit statuses are green"),
+        "pending" => Check::new(CheckState::Pending, "Commit statuses are green"),
+        _         => Check::new(CheckState::Failed, &format!("Commit statuses are green, failing: {}", failing.join(", ")))
+    }))
+}
+
+//Check runs of the commit, i.e Github Actions
+//Ok: None if there are none
+fn check_runs(client: &github::GithubClient, repo: &str, sha: &str) -> Result<Option<Check>, String> {
+
+    let runs = try!(client.get(&format!("repos/{}/commits/{}/check-runs?per_page=100", repo, sha)));
+    let runs = runs.find("check_runs").and_then(|runs| runs.as_array()).cloned().unwrap_or(Vec::new());
+    if runs.is_empty() {
+        return Ok(None)
+    }
+
+    let mut pending: Vec<String> = Vec::new();
+    let mut failing: Vec<String> = Vec::new();
+    for run in runs.iter() {
+        let name = response::inline_code(run.find("name").and_then(|name| name.as_str()).unwrap_or(""));
+        if run.find("status").and_then(|status| status.as_str()) != Some("completed") {
+            pending.push(name);
+        } else if !PASSING_CONCLUSIONS.iter().any(|conclusion| Some(*conclusion) == run.find("conclusion").and_then(|conclusion| conclusion.as_str())) {
+            failing.push(name);
+        }
+    }
+
+    Ok(Some(if !failing.is_empty() {
+        Check::new(CheckState::Failed, &format!("Check runs are green, failing: {}", failing.join(", ")))
+    } else if !pending.is_empty() {
+        Check::new(CheckState::Pending, &format!("Check runs are green, running: {}", pending.join(", ")))
+    } else {
+        Check::new(CheckState::Passed, "Check runs are green")
+    }))
+}
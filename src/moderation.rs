@@ -0,0 +1,362 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+//Triage of the issue or PR a command was given on: labels, closing and
+//reopening, locking and assignees. Github answers the calls made with the
+//token of the bot, which needs the matching permission on the repository.
+
+use std::collections::BTreeMap;
+
+extern crate hyper;
+use self::hyper::method::Method;
+
+extern crate serde_json;
+
+use commands;
+use permissions::Role;
+use response;
+use response::Message;
+
+include!("logger_macros.rs");
+
+//(reason of the command, "lock_reason" expected by Github)
+const LOCK_REASONS: &'static [(&'static str, &'static str)] = &[
+    ("off-topic",  "off-topic"),
+    ("too-heated", "too heated"),
+    ("resolved",   "resolved"),
+    ("spam",       "spam")
+];
+
+
+////////////////////////////////////////////////////////////
+//                        Commands                        //
+////////////////////////////////////////////////////////////
+
+///"label <add|remove> <label>..."
+pub struct LabelCommand;
+
+impl LabelCommand {
+    pub fn command() -> commands::Command {
+        commands::Command::new(false, Role::Triage, LabelCommand)
+            .summary("Adds labels to the issue or removes them, the labels have to exist in the repository.")
+            .usage("label <add|remove> <label>...")
+            .argument("add|remove", true, "What to do with the labels.")
+            .argument("label", true, "Names of the labels, quoted if they contain spaces.")
+            .example("label add bug \"help wanted\"")
+            .example("label remove needs-review")
+    }
+}
+
+impl commands::BotCommand for LabelCommand {
+    fn run(&self, context: &mut commands::CommandContext) -> Result<Message, String> {
+
+        thread_trace!("moderation.rs: LabelCommand::run(&self, context)");
+
+        let usage = format!("Usage: {}", response::inline_code("label <add|remove> <label>..."));
+        let add   = match context.args.positional(0) {
+            Some("add")    => true,
+            Some("remove") => false,
+            _              => return Err(usage)
+        };
+        if context.args.positional.len() < 2 {
+            return Err(usage)
+        }
+        let names  = context.args.positional[1..].to_vec();
+        let labels = try!(repository_labels(context, &names));
+        let issue  = issue_endpoint(context);
+
+        if add {
+            try!(context.github.post(&format!("{}/labels", issue), &json_string_array(&labels)));
+            return Ok(Message::text(&format!("Added {}.", code_list(&labels))))
+        }
+
+        //Removing a label the issue does not have is a 404, nothing to do then
+        for label in labels.iter() {
+            let (status, _) = try!(context.github.request(Method::Delete, &format!("{}/labels/{}", issue, encode_path_segment(label)), None));
+            if status / 100 != 2 && status != 404 {
+                return Err(format!("Failed to remove {}, status {}.", response::inline_code(label), status))
+            }
+        }
+        Ok(Message::text(&format!("Removed {}.", code_list(&labels))))
+    }
+}
+
+///"close [--reason completed|not-planned] [comment]"
+pub struct CloseCommand;
+
+impl CloseCommand {
+    pub fn command() -> commands::Command {
+        commands::Command::new(false, Role::IssueAuthor, CloseCommand)
+            .summary("Closes the issue or pull request.")
+            .usage("close [--reason completed|not-planned] [comment]")
+            .argument("--reason", false, "Why it is closed, \"completed\" by default.")
+            .argument("comment", false, "Explanation repeated in the reply.")
+            .example("close")
+            .example("close --reason not-planned Duplicate of #12")
+    }
+}
+
+impl commands::BotCommand for CloseCommand {
+    fn run(&self, context: &mut commands::CommandContext) -> Result<Message, String> {
+
+        thread_trace!("moderation.rs: CloseCommand::run(&self, context)");
+
+        let reason = match context.args.option("reason").unwrap_or("completed") {
+            "completed"   => "completed",
+            "not-planned" => "not_planned",
+            reason        => return Err(format!("{} is not a reason to close, use {} or {}.", response::inline_code(reason), response::inline_code("completed"), response::inline_code("not-planned")))
+        };
+        try!(set_state(context, "closed", reason));
+        Ok(with_comment(&format!("Closed as {}.", reason.replace("_", " ")), context))
+    }
+}
+
+///"reopen [comment]"
+pub struct ReopenCommand;
+
+impl ReopenCommand {
+    pub fn command() -> commands::Command {
+        commands::Command::new(false, Role::Triage, ReopenCommand)
+            .summary("Reopens the issue or pull request.")
+            .usage("reopen [comment]")
+            .argument("comment", false, "Explanation repeated in the reply.")
+            .example("reopen The fix was reverted")
+    }
+}
+
+impl commands::BotCommand for ReopenCommand {
+    fn run(&self, context: &mut commands::CommandContext) -> Result<Message, String> {
+        thread_trace!("moderation.rs: ReopenCommand::run(&self, context)");
+        try!(set_state(context, "open", "reopened"));
+        Ok(with_comment("Reopened.", context))
+    }
+}
+
+///"lock [off-topic|too-heated|resolved|spam]"
+pub struct LockCommand;
+
+impl LockCommand {
+    pub fn command() -> commands::Command {
+        commands::Command::new(false, Role::Write, LockCommand)
+            .summary("Locks the conversation, only collaborators can comment afterwards.")
+            .usage("lock [off-topic|too-heated|resolved|spam]")
+            .argument("off-topic|too-heated|resolved|spam", false, "Reason shown by Github on the conversation.")
+            .example("lock resolved")
+    }
+}
+
+impl commands::BotCommand for LockCommand {
+    fn run(&self, context: &mut commands::CommandContext) -> Result<Message, String> {
+
+        thread_trace!("moderation.rs: LockCommand::run(&self, context)");
+
+        let mut body: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+        let mut reason: Option<&'static str> = None;
+        if let Some(name) = context.args.positional(0) {
+            reason = match LOCK_REASONS.iter().find(|&&(known, _)| known == name) {
+                Some(&(_, lock_reason)) => Some(lock_reason),
+                None                    => return Err(format!("{} is not a lock reason, use one of {}.", response::inline_code(name),
+                    LOCK_REASONS.iter().map(|&(known, _)| response::inline_code(known)).collect::<Vec<String>>().join(", ")))
+            };
+        }
+        if let Some(reason) = reason {
+            body.insert(String::from("lock_reason"), serde_json::Value::String(String::from(reason)));
+        }
+
+        let endpoint = format!("{}/lock", issue_endpoint(context));
+        try!(context.github.put(&endpoint, &try!(to_json_string(&serde_json::Value::Object(body)))));
+        match reason {
+            Some(reason) => Ok(Message::text(&format!("Locked as {}.", reason))),
+            None         => Ok(Message::text("Locked."))
+        }
+    }
+}
+
+///"unlock"
+pub struct UnlockCommand;
+
+impl UnlockCommand {
+    pub fn command() -> commands::Command {
+        commands::Command::new(false, Role::Write, UnlockCommand)
+            .summary("Unlocks the conversation.")
+            .usage("unlock")
+            .example("unlock")
+    }
+}
+
+impl commands::BotCommand for UnlockCommand {
+    fn run(&self, context: &mut commands::CommandContext) -> Result<Message, String> {
+        thread_trace!("moderation.rs: UnlockCommand::run(&self, context)");
+        let endpoint = format!("{}/lock", issue_endpoint(context));
+        try!(context.github.delete(&endpoint));
+        Ok(Message::text("Unlocked."))
+    }
+}
+
+///"assign <user>..."
+pub struct AssignCommand;
+
+impl AssignCommand {
+    pub fn command() -> commands::Command {
+        commands::Command::new(false, Role::Triage, AssignCommand)
+            .summary("Assigns users to the issue or pull request.")
+            .usage("assign <user>...")
+            .argument("user", true, "Github users to assign, \"me\" is the one giving the command.")
+            .example("assign me")
+            .example("assign octocat ruslo")
+    }
+}
+
+impl commands::BotCommand for AssignCommand {
+    fn run(&self, context: &mut commands::CommandContext) -> Result<Message, String> {
+
+        thread_trace!("moderation.rs: AssignCommand::run(&self, context)");
+
+        let users    = try!(assignees(context, "assign <user>..."));
+        let body     = try!(assignees_json(&users));
+        let endpoint = format!("{}/assignees", issue_endpoint(context));
+        let issue    = try!(context.github.post(&endpoint, &body));
+
+        //Github silently skips the users that cannot be assigned
+        let assigned: Vec<String> = issue.find("assignees").and_then(|assignees| assignees.as_array()).map(|assignees| {
+            assignees.iter().filter_map(|assignee| assignee.find("login").and_then(|login| login.as_str()).map(|login| login.to_lowercase())).collect()
+        }).unwrap_or(Vec::new());
+        let skipped: Vec<String> = users.iter().filter(|user| !assigned.contains(&user.to_lowercase())).cloned().collect();
+
+        let assigned: Vec<String> = users.iter().filter(|user| !skipped.contains(user)).cloned().collect();
+        if assigned.is_empty() {
+            return Err(format!("Github did not assign {}, only collaborators and people who commented can be assigned.", code_list(&skipped)))
+        }
+        let message = Message::text(&format!("Assigned {}.", code_list(&assigned)));
+        if skipped.is_empty() {
+            Ok(message)
+        } else {
+            Ok(message.paragraph(&format!("Github did not assign {}, only collaborators and people who commented can be assigned.", code_list(&skipped))))
+        }
+    }
+}
+
+///"unassign <user>..."
+pub struct UnassignCommand;
+
+impl UnassignCommand {
+    pub fn command() -> commands::Command {
+        commands::Command::new(false, Role::Triage, UnassignCommand)
+            .summary("Removes users from the assignees of the issue or pull request.")
+            .usage("unassign <user>...")
+            .argument("user", true, "Github users to unassign, \"me\" is the one giving the command.")
+            .example("unassign me")
+    }
+}
+
+impl commands::BotCommand for UnassignCommand {
+    fn run(&self, context: &mut commands::CommandContext) -> Result<Message, String> {
+
+        thread_trace!("moderation.rs: UnassignCommand::run(&self, context)");
+
+        let users       = try!(assignees(context, "unassign <user>..."));
+        let body        = try!(assignees_json(&users));
+        let endpoint    = format!("{}/assignees", issue_endpoint(context));
+        let (status, _) = try!(context.github.request(Method::Delete, &endpoint, Some(&body)));
+        if status / 100 != 2 {
+            return Err(format!("Failed to unassign {}, status {}.", code_list(&users), status))
+        }
+        Ok(Message::text(&format!("Unassigned {}.", code_list(&users))))
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                          Funcs                         //
+////////////////////////////////////////////////////////////
+
+//repos/{repo}/issues/{number}, also the endpoint of pull requests for these calls
+fn issue_endpoint(context: &mut commands::CommandContext) -> String {
+    let repo = context.config.get_string_required("config", "github_follow_repo");
+    format!("repos/{}/issues/{}", repo, context.event.number)
+}
+
+//The labels as spelled in the repository, Err if one of them does not exist
+fn repository_labels(context: &mut commands::CommandContext, names: &Vec<String>) -> Result<Vec<String>, String> {
+
+    let repo     = context.config.get_string_required("config", "github_follow_repo");
+    let existing = try!(context.github.get_all(&format!("repos/{}/labels", repo)));
+    let existing: Vec<String> = existing.iter()
+        .filter_map(|label| label.find("name").and_then(|name| name.as_str()).map(String::from))
+        .collect();
+
+    let mut labels:  Vec<String> = Vec::new();
+    let mut unknown: Vec<String> = Vec::new();
+    for name in names {
+        match existing.iter().find(|label| label.to_lowercase() == name.to_lowercase()) {
+            Some(label) => labels.push(label.clone()),
+            None        => unknown.push(name.clone())
+        }
+    }
+
+    if !unknown.is_empty() {
+        return Err(format!("The repository has no label {}. Its labels are: {}.", code_list(&unknown), code_list(&existing)))
+    }
+    Ok(labels)
+}
+
+fn set_state(context: &mut commands::CommandContext, state: &str, reason: &str) -> Result<(), String> {
+    let mut body: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+    body.insert(String::from("state"),        serde_json::Value::String(String::from(state)));
+    body.insert(String::from("state_reason"), serde_json::Value::String(String::from(reason)));
+    let body     = try!(to_json_string(&serde_json::Value::Object(body)));
+    let endpoint = issue_endpoint(context);
+    try!(context.github.patch(&endpoint, &body));
+    Ok(())
+}
+
+//Reply followed by the words after the options of the command, if any
+fn with_comment(text: &str, context: &commands::CommandContext) -> Message {
+    let comment = context.args.positional.join(" ");
+    if comment.is_empty() {
+        Message::text(text)
+    } else {
+        Message::text(text).paragraph(&format!("> {}", comment))
+    }
+}
+
+//Users of an assign/unassign command, "me" being the user who gave it
+fn assignees(context: &commands::CommandContext, usage: &str) -> Result<Vec<String>, String> {
+    if context.args.positional.is_empty() {
+        return Err(format!("Usage: {}", response::inline_code(usage)))
+    }
+    Ok(context.args.positional.iter()
+        .map(|user| if user.to_lowercase() == "me" {context.event.user.clone()} else {user.trim_left_matches('@').to_string()})
+        .collect())
+}
+
+fn assignees_json(users: &Vec<String>) -> Result<String, String> {
+    let mut body: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+    body.insert(String::from("assignees"), serde_json::Value::Array(users.iter().map(|user| serde_json::Value::String(user.clone())).collect()));
+    to_json_string(&serde_json::Value::Object(body))
+}
+
+fn json_string_array(items: &Vec<String>) -> String {
+    serde_json::to_string(&serde_json::Value::Array(items.iter().map(|item| serde_json::Value::String(item.clone())).collect())).unwrap_or(String::from("[]"))
+}
+
+fn to_json_string(value: &serde_json::Value) -> Result<String, String> {
+    serde_json::to_string(value).map_err(|err| format!("Failed to serialize the request: {}", err))
+}
+
+//"`a`, `b`"
+fn code_list(items: &Vec<String>) -> String {
+    items.iter().map(|item| response::inline_code(item)).collect::<Vec<String>>().join(", ")
+}
+
+//Percent-encodes everything but the unreserved characters, i.e label names with spaces
+fn encode_path_segment(segment: &str) -> String {
+    let mut encoded = String::new();
+    for byte in segment.bytes() {
+        match byte {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            _                                                                 => encoded.push_str(&format!("%{:02X}", byte))
+        }
+    }
+    encoded
+}
@@ -0,0 +1,367 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex};
+
+extern crate chrono;
+use self::chrono::*;
+
+extern crate serde_json;
+
+use config;
+use github;
+use sticky;
+use store;
+use webhooks;
+
+include!("logger_macros.rs");
+
+const QUEUE_FILE_NAME:       &'static str = "outbound-queue.json";
+const DEAD_LETTER_FILE_NAME: &'static str = "outbound-dead-letter.json";
+
+
+////////////////////////////////////////////////////////////
+//                    OutboundMessage                     //
+////////////////////////////////////////////////////////////
+
+/// OutboundMessage
+/// id:           Sequence number, messages are delivered in that order
+/// endpoint:     API endpoint without the access token, i.e repos/{repo}/issues/{number}/comments
+/// body:         JSON body of the request
+/// sticky:       Tag of the sticky comment to edit instead of posting a new one, empty if none
+/// attempts:     Number of failed delivery attempts
+/// next_attempt: Unix timestamp of the next delivery attempt
+/// last_error:   Error of the last failed attempt
+#[derive(Clone, Debug)]
+pub struct OutboundMessage {
+    pub id:           u64,
+    pub endpoint:     String,
+    pub body:         String,
+    pub sticky:       String,
+    pub attempts:     u64,
+    pub next_attempt: i64,
+    pub last_error:   String
+}
+
+impl OutboundMessage {
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut object: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+        object.insert(String::from("id"),           serde_json::Value::U64(self.id));
+        object.insert(String::from("endpoint"),     serde_json::Value::String(self.endpoint.clone()));
+        object.insert(String::from("body"),         serde_json::Value::String(self.body.clone()));
+        object.insert(String::from("sticky"),       serde_json::Value::String(self.sticky.clone()));
+        object.insert(String::from("attempts"),     serde_json::Value::U64(self.attempts));
+        object.insert(String::from("next_attempt"), serde_json::Value::I64(self.next_attempt));
+        object.insert(String::from("last_error"),   serde_json::Value::String(self.last_error.clone()));
+        serde_json::Value::Object(object)
+    }
+
+    pub fn from_json(value: &serde_json::Value) -> Result<OutboundMessage, String> {
+        let object = try!(webhooks::extract_json_object(value));
+        Ok(OutboundMessage {
+            id:           try!(webhooks::extract_json_u64(&object, "id")),
+            endpoint:     try!(webhooks::extract_json_string(&object, "endpoint")),
+            body:         try!(webhooks::extract_json_string(&object, "body")),
+            sticky:       webhooks::extract_json_string(&object, "sticky").unwrap_or(String::new()),
+            attempts:     try!(webhooks::extract_json_u64(&object, "attempts")),
+            next_attempt: try!(object.get("next_attempt").and_then(|value| value.as_i64()).ok_or(String::from("The \"next_attempt\" field does not describe a number."))),
+            last_error:   try!(webhooks::extract_json_string(&object, "last_error"))
+        })
+    }
+
+    //Text of the comment, for display purposes
+    pub fn comment_body(&self) -> String {
+        match serde_json::from_str::<serde_json::Value>(&self.body[..]) {
+            Ok(value) => {
+                match value.find("body").and_then(|body| body.as_str()) {
+                    Some(body) => String::from(body),
+                    None       => self.body.clone()
+                }
+            }
+            Err(_)    => self.body.clone()
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                     OutboundQueue                      //
+////////////////////////////////////////////////////////////
+
+struct OutboundState {
+    messages:         Vec<OutboundMessage>,
+    next_id:          u64,
+    store:            store::Store
+}
+
+///Persistent queue of the requests the bot makes on its own behalf (replies).
+///Messages are written to "outbound-queue.json" in "data_dir" before being
+///sent, retried with an exponential backoff ("outbound_retry_delay" seconds,
+///doubled on every attempt) and moved to "outbound-dead-letter.json" after
+///"outbound_max_attempts" failures.
+#[derive(Clone)]
+pub struct OutboundQueue {
+    config: Arc<Mutex<config::ConfigHandler>>,
+    state:  Arc<(Mutex<OutboundState>, Condvar)>
+}
+
+impl OutboundQueue {
+
+    //Loads the pending messages left over from a previous run and starts the delivery thread
+    pub fn start(tsconfig: &Arc<Mutex<config::ConfigHandler>>) -> OutboundQueue {
+
+        thread_trace!("outbound.rs: OutboundQueue::start(tsconfig)");
+
+        let store = store::Store::new(tsconfig);
+
+        let messages = match load_messages(&store) {
+            Ok(messages) => messages,
+            Err(err)     => {thread_crash!("Failed to load the outbound queue from \"{}\": {}", store.path(QUEUE_FILE_NAME).display(), err);}
+        };
+        if !messages.is_empty() {
+            thread_info!("Resuming delivery of {} pending message(s).", messages.len());
+        }
+        let next_id = messages.iter().map(|message| message.id + 1).max().unwrap_or(0);
+
+        let queue = OutboundQueue {
+            config: tsconfig.clone(),
+            state:  Arc::new((Mutex::new(OutboundState {
+                messages:         messages,
+                next_id:          next_id,
+                store:            store
+            }), Condvar::new()))
+        };
+
+        let worker = queue.clone();
+        match thread::Builder::new().name(String::from("outbound")).spawn(move || worker.deliver()) {
+            Ok(_)    => (),
+            Err(err) => {thread_crash!("Failed to start the outbound thread: {}", err);}
+        }
+
+        queue
+    }
+
+    //Queues a POST request, identical requests already waiting for delivery are collapsed
+    pub fn push(&self, endpoint: String, body: String) {
+        thread_trace!("outbound.rs: OutboundQueue::push(&self, {}, {})", endpoint, body);
+        self.enqueue(endpoint, body, String::new());
+    }
+
+    //Queues an update of the sticky comment tagged `tag` on the issue of the
+    //comments endpoint, a pending update of the same comment is superseded
+    pub fn push_sticky(&self, endpoint: String, tag: String, body: String) {
+        thread_trace!("outbound.rs: OutboundQueue::push_sticky(&self, {}, {}, {})", endpoint, tag, body);
+        self.enqueue(endpoint, body, tag);
+    }
+
+    fn enqueue(&self, endpoint: String, body: String, sticky: String) {
+
+        let &(ref lock, ref condvar) = &*self.state;
+        let mut state                = lock.lock().unwrap();
+
+        thread_trace!("  Is duplicate test");
+        if state.messages.iter().any(|message| message.endpoint == endpoint && message.body == body && message.sticky == sticky) {
+            thread_debug!("Dropping duplicate outbound message to {}", endpoint);
+            return;
+        }
+        thread_trace!("    false");
+
+        if sticky != String::new() {
+            thread_trace!("  Is pending sticky update test");
+            let mut superseded = false;
+            for message in state.messages.iter_mut() {
+                if message.endpoint == endpoint && message.sticky == sticky {
+                    message.body = body.clone();
+                    superseded   = true;
+                }
+            }
+            if superseded {
+                thread_debug!("Superseding the pending update of sticky comment {} on {}", sticky, endpoint);
+                persist(&state);
+                condvar.notify_one();
+                return;
+            }
+            thread_trace!("    false");
+        }
+
+        let id         = state.next_id;
+        state.next_id += 1;
+        state.messages.push(OutboundMessage {
+            id:           id,
+            endpoint:     endpoint,
+            body:         body,
+            sticky:       sticky,
+            attempts:     0,
+            next_attempt: UTC::now().timestamp(),
+            last_error:   String::new()
+        });
+        persist(&state);
+        condvar.notify_one();
+    }
+
+    pub fn pending(&self) -> usize {
+        let &(ref lock, _) = &*self.state;
+        lock.lock().unwrap().messages.len()
+    }
+
+    fn wait_for_due_message(&self) -> OutboundMessage {
+        let &(ref lock, ref condvar) = &*self.state;
+        let mut state                = lock.lock().unwrap();
+        loop {
+            let now = UTC::now().timestamp();
+            match next_due_message(&state.messages, now) {
+                Some(message) => return message,
+                None          => {
+                    let wait = state.messages.iter().map(|message| message.next_attempt - now).min().unwrap_or(60);
+                    state    = condvar.wait_timeout(state, time::Duration::from_secs(cmp::max(wait, 1) as u64)).unwrap().0;
+                }
+            }
+        }
+    }
+
+    fn deliver(&self) {
+
+        let &(ref lock, _) = &*self.state;
+
+        loop {
+
+            let message = self.wait_for_due_message();
+
+            //Send without holding the lock so that replies can still be queued
+            let bot_name:     String;
+            let max_attempts: u64;
+            let retry_delay:  i64;
+            {
+                let mut config = self.config.lock().unwrap();
+                bot_name       = config.get_string_required("config", "github_bot_name");
+                max_attempts   = config.get_string_or("config", "outbound_max_attempts", "5").parse().unwrap_or(5);
+                retry_delay    = config.get_string_or("config", "outbound_retry_delay", "2").parse().unwrap_or(2);
+            }
+
+            thread_debug!("Delivering outbound message {} to {} (attempt {})", message.id, message.endpoint, message.attempts + 1);
+            let client = github::GithubClient::bot(&self.config);
+            let result = if message.sticky == String::new() {
+                client.post(&message.endpoint, &message.body).map(|_| ())
+            } else {
+                sticky::upsert(&client, &bot_name, &message.endpoint, &message.sticky, &message.body)
+            };
+
+            let mut state = lock.lock().unwrap();
+            match result {
+                Ok(()) => {
+                    thread_trace!("  Delivered");
+                    //A sticky update superseded while in flight stays queued with its new body
+                    let superseded = state.messages.iter().any(|pending| pending.id == message.id && pending.body != message.body);
+                    if !superseded {
+                        state.messages.retain(|pending| pending.id != message.id);
+                    }
+                }
+                Err(err) => {
+                    let mut failed = match state.messages.iter().find(|pending| pending.id == message.id) {
+                        Some(pending) => pending.clone(),
+                        None          => message.clone()
+                    };
+                    failed.attempts   += 1;
+                    failed.last_error = err.clone();
+
+                    if failed.attempts >= max_attempts {
+                        thread_error!("Giving up on outbound message {} to {} after {} attempt(s): {}", failed.id, failed.endpoint, failed.attempts, err);
+                        match append_dead_letter(&state.store, &failed) {
+                            Ok(())   => (),
+                            Err(err) => {thread_error!("Failed to record the dead letter: {}", err);}
+                        }
+                        state.messages.retain(|pending| pending.id != failed.id);
+                    } else {
+                        let delay = retry_delay * (1 << cmp::min(failed.attempts - 1, 16));
+                        thread_warn!("Failed to deliver outbound message {} to {}, retrying in {}s: {}", failed.id, failed.endpoint, delay, err);
+                        failed.next_attempt = UTC::now().timestamp() + delay;
+                        for pending in state.messages.iter_mut() {
+                            if pending.id == failed.id {
+                                *pending = failed.clone();
+                            }
+                        }
+                    }
+                }
+            }
+            persist(&state);
+        }
+    }
+}
+
+impl fmt::Debug for OutboundQueue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "OutboundQueue {{pending: {}}}", self.pending())
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                          Utils                         //
+////////////////////////////////////////////////////////////
+
+//Messages that could not be delivered, oldest first, along with the time they were given up on
+pub fn dead_letters(tsconfig: &Arc<Mutex<config::ConfigHandler>>) -> Result<Vec<(i64, OutboundMessage)>, String> {
+    let mut dead_letters: Vec<(i64, OutboundMessage)> = Vec::new();
+    for value in try!(store::Store::new(tsconfig).entries(DEAD_LETTER_FILE_NAME)) {
+        let failed_at = try!(value.find("failed_at").and_then(|failed_at| failed_at.as_i64()).ok_or(String::from("The \"failed_at\" field does not describe a number.")));
+        let message   = try!(OutboundMessage::from_json(try!(value.find("message").ok_or(String::from("The \"message\" field was not found in the JSON object.")))));
+        dead_letters.push((failed_at, message));
+    }
+    Ok(dead_letters)
+}
+
+//First message that is due, messages to the same endpoint stay in order
+fn next_due_message(messages: &Vec<OutboundMessage>, now: i64) -> Option<OutboundMessage> {
+    let mut blocked: Vec<&String> = Vec::new();
+    for message in messages {
+        if blocked.contains(&&message.endpoint) {
+            continue;
+        }
+        if message.next_attempt <= now {
+            return Some(message.clone());
+        }
+        blocked.push(&message.endpoint);
+    }
+    None
+}
+
+//Written through the store, a crash while saving leaves the previous queue
+fn persist(state: &OutboundState) {
+    let values: Vec<serde_json::Value> = state.messages.iter().map(|message| message.to_json()).collect();
+    if let Err(err) = state.store.save(QUEUE_FILE_NAME, &serde_json::Value::Array(values)) {
+        thread_error!("Failed to save the outbound queue: {}", err);
+    }
+}
+
+fn load_messages(store: &store::Store) -> Result<Vec<OutboundMessage>, String> {
+
+    thread_trace!("outbound.rs: load_messages(store)");
+
+    let value = match try!(store.load(QUEUE_FILE_NAME)) {
+        Some(value) => value,
+        None        => {
+            thread_trace!("Return Ok(empty)");
+            return Ok(Vec::new())
+        }
+    };
+    let array = try!(value.as_array().ok_or(String::from("The outbound queue does not describe an array.")));
+
+    let mut messages: Vec<OutboundMessage> = Vec::new();
+    for item in array {
+        messages.push(try!(OutboundMessage::from_json(item)));
+    }
+    messages.sort_by_key(|message| message.id);
+    Ok(messages)
+}
+
+//Dead letters are stored one JSON object per line: {"failed_at": ..., "message": {...}}
+fn append_dead_letter(store: &store::Store, message: &OutboundMessage) -> Result<(), String> {
+    let mut object: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+    object.insert(String::from("failed_at"), serde_json::Value::I64(UTC::now().timestamp()));
+    object.insert(String::from("message"),   message.to_json());
+    store.append(DEAD_LETTER_FILE_NAME, &serde_json::Value::Object(object))
+}
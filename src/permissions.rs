@@ -0,0 +1,195 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+//Who can run what: every command declares the minimum role it needs and the
+//role of the user is worked out from the config (owner), the event (issue
+//author) and the collaborator permission of the user on the followed
+//repository, which is fetched from the Github API and cached for
+//"permission_cache_ttl" seconds (300 by default). The whitelist is not a
+//role: it only lets its users run the commands accepting it.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+extern crate chrono;
+use self::chrono::*;
+
+extern crate hyper;
+use self::hyper::method::Method;
+
+use config;
+use github;
+use webhooks;
+
+include!("logger_macros.rs");
+
+
+////////////////////////////////////////////////////////////
+//                          Role                          //
+////////////////////////////////////////////////////////////
+
+///Roles in increasing order, a user with a role can run the commands
+///requiring it or any role below it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Anyone,
+    IssueAuthor,
+    Triage,
+    Write,
+    Maintain,
+    Admin,
+    Owner
+}
+
+impl Role {
+
+    //Collaborator permission as returned by the Github API
+    pub fn from_permission(permission: &str) -> Role {
+        match permission {
+            "triage"   => Role::Triage,
+            "write"    => Role::Write,
+            "push"     => Role::Write,
+            "maintain" => Role::Maintain,
+            "admin"    => Role::Admin,
+            _          => Role::Anyone
+        }
+    }
+
+    //Who has the role, as shown in the help
+    pub fn description(&self) -> &'static str {
+        match *self {
+            Role::Anyone      => "Anyone",
+            Role::IssueAuthor => "The issue author and collaborators",
+            Role::Triage      => "Collaborators with triage permission or higher",
+            Role::Write       => "Collaborators with write permission or higher",
+            Role::Maintain    => "Collaborators with maintain permission or higher",
+            Role::Admin       => "Collaborators with admin permission",
+            Role::Owner       => "The owner"
+        }
+    }
+
+    //Completes "You need to be ... to run that command"
+    pub fn requirement(&self) -> &'static str {
+        match *self {
+            Role::Anyone      => "anyone",
+            Role::IssueAuthor => "the issue author or a collaborator",
+            Role::Triage      => "a collaborator with triage permission",
+            Role::Write       => "a collaborator with write permission",
+            Role::Maintain    => "a collaborator with maintain permission",
+            Role::Admin       => "a collaborator with admin permission",
+            Role::Owner       => "the owner"
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                    PermissionCache                     //
+////////////////////////////////////////////////////////////
+
+///Collaborator role of users by login along with the time it was fetched.
+///Clones share the same cache.
+#[derive(Clone, Debug)]
+pub struct PermissionCache {
+    config:  Arc<Mutex<config::ConfigHandler>>,
+    entries: Arc<Mutex<BTreeMap<String, (Role, i64)>>>
+}
+
+impl PermissionCache {
+
+    pub fn new(tsconfig: &Arc<Mutex<config::ConfigHandler>>) -> PermissionCache {
+        PermissionCache {
+            config:  tsconfig.clone(),
+            entries: Arc::new(Mutex::new(BTreeMap::new()))
+        }
+    }
+
+    //Does the user who sent the event have at least the given role?
+    //The Github API is only asked when the config and the event are not enough.
+    pub fn has_role(&self, event: &webhooks::WebhookEvent, minimum: Role) -> bool {
+        if minimum == Role::Anyone {
+            return true
+        }
+        self.user_role(event, minimum) >= minimum
+    }
+
+    //Is the user who sent the event in the whitelist? The owner always is.
+    pub fn is_whitelisted(&self, event: &webhooks::WebhookEvent) -> bool {
+        self.config.lock().unwrap().whitelist_validate_user(event.user.clone())
+    }
+
+    //Highest role of the user who sent the event, the collaborator permission
+    //is not looked up if a role of at least `enough` is already known
+    pub fn user_role(&self, event: &webhooks::WebhookEvent, enough: Role) -> Role {
+
+        thread_trace!("permissions.rs: PermissionCache::user_role(&self, event, {:?})", enough);
+
+        if self.config.lock().unwrap().get_string_required("config", "github_owner_name") == event.user {
+            thread_trace!("Return Owner");
+            return Role::Owner
+        }
+
+        let mut role = Role::Anyone;
+        if !event.issue_author.is_empty() && event.issue_author == event.user {
+            role = Role::IssueAuthor;
+        }
+        if role >= enough {
+            thread_trace!("Return {:?}", role);
+            return role
+        }
+
+        match self.collaborator_role(&event.user) {
+            Ok(collaborator_role) => {
+                if collaborator_role > role {
+                    role = collaborator_role;
+                }
+            }
+            Err(err)              => {thread_warn!("Failed to get the permission of {}: {}", event.user, err);}
+        }
+
+        thread_debug!("Role of {}: {:?}", event.user, role);
+        role
+    }
+
+    //Collaborator role of the user on the followed repository, cached
+    pub fn collaborator_role(&self, user: &str) -> Result<Role, String> {
+
+        thread_trace!("permissions.rs: PermissionCache::collaborator_role(&self, \"{}\")", user);
+
+        let github_follow_repo: String;
+        let ttl:                i64;
+        {
+            let mut config     = self.config.lock().unwrap();
+            github_follow_repo = config.get_string_required("config", "github_follow_repo");
+            ttl                = config.get_string_or("config", "permission_cache_ttl", "300").parse().unwrap_or(300);
+        }
+
+        let now = UTC::now().timestamp();
+        if let Some(&(role, fetched_at)) = self.entries.lock().unwrap().get(user) {
+            if now - fetched_at < ttl {
+                thread_trace!("Return cached Ok({:?})", role);
+                return Ok(role)
+            }
+        }
+
+        //Listing the permission needs push access, hence the owner token
+        let client          = github::GithubClient::owner(&self.config);
+        let endpoint        = format!("repos/{}/collaborators/{}/permission", github_follow_repo, user);
+        let (status, value) = try!(client.request(Method::Get, &endpoint, None));
+        let role            = match status {
+            200 => {
+                //"role_name" tells triage and maintain apart, "permission" folds them into read and write
+                let permission = value.find("role_name").and_then(|role_name| role_name.as_str())
+                    .or(value.find("permission").and_then(|permission| permission.as_str()))
+                    .unwrap_or("none");
+                Role::from_permission(permission)
+            }
+            404 => Role::Anyone,
+            _   => return Err(format!("GET {} failed with status {}", endpoint, status))
+        };
+
+        self.entries.lock().unwrap().insert(String::from(user), (role, now));
+        thread_trace!("Return Ok({:?})", role);
+        Ok(role)
+    }
+}
@@ -0,0 +1,586 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+//Merge queue: "r+" queues a pull request, the bot then merges the PRs one
+//at a time. The PR at the front is merged onto the latest base branch in a
+//staging branch, once CI is green on it the base branch is fast-forwarded to
+//it. Two PRs that are green on their own but break together are caught
+//before the base branch sees them. A PR CI does not report on within
+//"queue_timeout" seconds is taken out, so the queue does not wait forever.
+//The queue is kept in "data_dir"/queue.json. A dry run leaves it as it is,
+//the made up replies of Github would stage and drop the PRs.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+extern crate chrono;
+use self::chrono::*;
+
+extern crate hyper;
+use self::hyper::method::Method;
+
+extern crate serde_json;
+
+use commands;
+use config;
+use github;
+use merge;
+use merge::CheckState;
+use outbound;
+use permissions::Role;
+use response;
+use response::Message;
+use store;
+use webhooks;
+
+include!("logger_macros.rs");
+
+const QUEUE_FILE_NAME: &'static str = "queue.json";
+
+
+////////////////////////////////////////////////////////////
+//                       QueueEntry                       //
+////////////////////////////////////////////////////////////
+
+/// QueueEntry
+/// number:      PR to merge
+/// title:       Title of the PR, for the merge commit
+/// head_sha:    Commit of the PR that was approved, later pushes are not merged
+/// approved_by: User who gave "r+", told how it went
+/// queued_at:   Unix timestamp
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueueEntry {
+    pub number:      u64,
+    pub title:       String,
+    pub head_sha:    String,
+    pub approved_by: String,
+    pub queued_at:   i64
+}
+
+impl QueueEntry {
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut object: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+        object.insert(String::from("number"),      serde_json::Value::U64(self.number));
+        object.insert(String::from("title"),       serde_json::Value::String(self.title.clone()));
+        object.insert(String::from("head_sha"),    serde_json::Value::String(self.head_sha.clone()));
+        object.insert(String::from("approved_by"), serde_json::Value::String(self.approved_by.clone()));
+        object.insert(String::from("queued_at"),   serde_json::Value::I64(self.queued_at));
+        serde_json::Value::Object(object)
+    }
+
+    pub fn from_json(value: &serde_json::Value) -> Result<QueueEntry, String> {
+        let object = try!(value.as_object().ok_or(String::from("A queue entry does not describe an object.")));
+        Ok(QueueEntry {
+            number:      try!(value.find("number").and_then(|number| number.as_u64()).ok_or(String::from("A queue entry has no number."))),
+            title:       try!(webhooks::extract_json_string(object, "title")),
+            head_sha:    try!(webhooks::extract_json_string(object, "head_sha")),
+            approved_by: try!(webhooks::extract_json_string(object, "approved_by")),
+            queued_at:   value.find("queued_at").and_then(|queued_at| queued_at.as_i64()).unwrap_or(0)
+        })
+    }
+}
+
+/// Staging
+/// entry:      PR being tested
+/// sha:        Merge commit of the PR onto the base branch, CI runs on it
+/// base_sha:   Commit of the base branch it was merged onto
+/// started_at: Unix timestamp, the PR is dropped if CI did not report after "queue_timeout" seconds
+#[derive(Clone, Debug, PartialEq)]
+pub struct Staging {
+    pub entry:      QueueEntry,
+    pub sha:        String,
+    pub base_sha:   String,
+    pub started_at: i64
+}
+
+/// QueueState
+/// staging: PR being tested, if any
+/// entries: PRs waiting, first in first out
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueueState {
+    pub staging: Option<Staging>,
+    pub entries: Vec<QueueEntry>
+}
+
+impl QueueState {
+
+    pub fn load(tsconfig: &Arc<Mutex<config::ConfigHandler>>) -> Result<QueueState, String> {
+
+        let mut state = QueueState {
+            staging: None,
+            entries: Vec::new()
+        };
+        let value = match try!(store::Store::new(tsconfig).load(QUEUE_FILE_NAME)) {
+            Some(value) => value,
+            None        => return Ok(state)
+        };
+
+        match value.find("staging") {
+            Some(&serde_json::Value::Object(ref staging)) => {
+                state.staging = Some(Staging {
+                    entry:      try!(QueueEntry::from_json(try!(staging.get("entry").ok_or(String::from("The staged PR has no entry."))))),
+                    sha:        try!(webhooks::extract_json_string(staging, "sha")),
+                    base_sha:   try!(webhooks::extract_json_string(staging, "base_sha")),
+                    started_at: staging.get("started_at").and_then(|started_at| started_at.as_i64()).unwrap_or(0)
+                });
+            }
+            _                                             => ()
+        }
+        for entry in value.find("entries").and_then(|entries| entries.as_array()).cloned().unwrap_or(Vec::new()) {
+            state.entries.push(try!(QueueEntry::from_json(&entry)));
+        }
+        Ok(state)
+    }
+
+    pub fn save(&self, tsconfig: &Arc<Mutex<config::ConfigHandler>>) -> Result<(), String> {
+
+        if tsconfig.lock().unwrap().is_dry_run() {
+            thread_info!("Dry run, the merge queue is not saved");
+            return Ok(())
+        }
+
+        let staging = match self.staging {
+            Some(ref staging) => {
+                let mut object: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+                object.insert(String::from("entry"),      staging.entry.to_json());
+                object.insert(String::from("sha"),        serde_json::Value::String(staging.sha.clone()));
+                object.insert(String::from("base_sha"),   serde_json::Value::String(staging.base_sha.clone()));
+                object.insert(String::from("started_at"), serde_json::Value::I64(staging.started_at));
+                serde_json::Value::Object(object)
+            }
+            None              => serde_json::Value::Null
+        };
+
+        let mut object: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+        object.insert(String::from("staging"), staging);
+        object.insert(String::from("entries"), serde_json::Value::Array(self.entries.iter().map(|entry| entry.to_json()).collect()));
+        store::Store::new(tsconfig).save(QUEUE_FILE_NAME, &serde_json::Value::Object(object))
+            .map_err(|err| format!("Failed to save the merge queue: {}", err))
+    }
+
+    //Whether the PR is staged or waiting
+    pub fn contains(&self, number: u64) -> bool {
+        self.staging.as_ref().map(|staging| staging.entry.number == number).unwrap_or(false) ||
+            self.entries.iter().any(|entry| entry.number == number)
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                        Commands                        //
+////////////////////////////////////////////////////////////
+
+///"r+"
+pub struct ApproveCommand;
+
+impl ApproveCommand {
+    pub fn command() -> commands::Command {
+        commands::Command::new(false, Role::Write, ApproveCommand)
+            .summary("Approves the pull request and adds it to the merge queue, it is tested on top of the base branch before being merged.")
+            .usage("r+")
+            .example("r+")
+    }
+}
+
+impl commands::BotCommand for ApproveCommand {
+    fn run(&self, context: &mut commands::CommandContext) -> Result<Message, String> {
+
+        thread_trace!("queue.rs: ApproveCommand::run(&self, context)");
+
+        let repo   = context.config.get_string_required("config", "github_follow_repo");
+        let number = context.event.number;
+        let pull   = try!(merge::PullRequest::fetch(&context.github, &repo, number));
+        if pull.state != "open" || pull.draft {
+            return Err(format!("Only open pull requests that are not drafts can be queued, #{} is {}.", number, if pull.draft {"a draft"} else {"closed"}))
+        }
+
+        let mut state = try!(QueueState::load(&context.tsconfig));
+        if state.contains(number) {
+            return Err(format!("#{} is in the merge queue already, say {} to take it out.", number, response::inline_code("r-")))
+        }
+        state.entries.push(QueueEntry {
+            number:      number,
+            title:       pull.title.clone(),
+            head_sha:    pull.head_sha.clone(),
+            approved_by: context.event.user.clone(),
+            queued_at:   UTC::now().timestamp()
+        });
+        let position = state.entries.len() + if state.staging.is_some() {1} else {0};
+        try!(state.save(&context.tsconfig));
+
+        //Replied first, testing starts right away if the queue was idle and says so
+        context.replies.send(Message::text(&format!("Added #{} ({}) to the merge queue at position {}.", number, short_sha(&pull.head_sha), position)));
+        advance(&context.tsconfig, &context.outbound, &context.github);
+        Ok(Message::new())
+    }
+}
+
+///"r-"
+pub struct UnapproveCommand;
+
+impl UnapproveCommand {
+    pub fn command() -> commands::Command {
+        commands::Command::new(false, Role::Write, UnapproveCommand)
+            .summary("Takes the pull request out of the merge queue, testing it is stopped.")
+            .usage("r-")
+            .example("r-")
+    }
+}
+
+impl commands::BotCommand for UnapproveCommand {
+    fn run(&self, context: &mut commands::CommandContext) -> Result<Message, String> {
+
+        thread_trace!("queue.rs: UnapproveCommand::run(&self, context)");
+
+        let number = context.event.number;
+        if try!(remove(&context.tsconfig, number)).is_none() {
+            return Err(format!("#{} is not in the merge queue.", number))
+        }
+        advance(&context.tsconfig, &context.outbound, &context.github);
+        Ok(Message::text(&format!("Took #{} out of the merge queue.", number)))
+    }
+}
+
+///"queue"
+pub struct QueueCommand;
+
+impl QueueCommand {
+    pub fn command() -> commands::Command {
+        commands::Command::new(false, Role::Anyone, QueueCommand)
+            .summary("Lists the pull requests in the merge queue, the one being tested first.")
+            .usage("queue")
+            .example("queue")
+    }
+}
+
+impl commands::BotCommand for QueueCommand {
+    fn run(&self, context: &mut commands::CommandContext) -> Result<Message, String> {
+
+        thread_trace!("queue.rs: QueueCommand::run(&self, context)");
+
+        expire_staging(&context.tsconfig, &context.outbound, &context.github);
+        let state = try!(QueueState::load(&context.tsconfig));
+        if state.staging.is_none() && state.entries.is_empty() {
+            return Ok(Message::text("The merge queue is empty."))
+        }
+
+        let mut rows: Vec<Vec<String>> = Vec::new();
+        if let Some(ref staging) = state.staging {
+            rows.push(entry_row(1, &staging.entry, &format!("Testing {}", short_sha(&staging.sha))));
+        }
+        for entry in state.entries.iter() {
+            let position = rows.len() + 1;
+            rows.push(entry_row(position, entry, "Waiting"));
+        }
+        Ok(Message::text(&format!("{} pull request(s) in the merge queue:", rows.len()))
+            .table(vec!["#", "Pull request", "Approved by", "Commit", "Status"], rows))
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                          Funcs                         //
+////////////////////////////////////////////////////////////
+
+//Follows the queue: CI results of the staging commit, pushes to and
+//closing of the queued PRs
+pub fn on_event(tsconfig: &Arc<Mutex<config::ConfigHandler>>, outbound: &outbound::OutboundQueue, event: &webhooks::WebhookEvent) {
+
+    thread_trace!("queue.rs: on_event(tsconfig, outbound, event)");
+
+    let client = github::GithubClient::bot(tsconfig);
+    expire_staging(tsconfig, outbound, &client);
+
+    let state = match QueueState::load(tsconfig) {
+        Ok(state) => state,
+        Err(err)  => {
+            thread_error!("{}", err);
+            return;
+        }
+    };
+
+    match event.event_type {
+        //The approval was for the commit that was pushed before
+        webhooks::WebhookEventType::PullRequest => {
+            if !state.contains(event.number) || (event.action != "synchronize" && event.action != "closed") {
+                return;
+            }
+            let entry = match remove(tsconfig, event.number) {
+                Ok(Some(entry)) => entry,
+                Ok(None)        => return,
+                Err(err)        => {
+                    thread_error!("{}", err);
+                    return;
+                }
+            };
+            let reason = if event.action == "closed" {String::from("it was closed")} else {format!("@{} pushed new commits", event.user)};
+            report(tsconfig, outbound, &entry, Message::text(&format!("Took #{} out of the merge queue, {}.", entry.number, reason)));
+            advance(tsconfig, outbound, &client);
+        }
+        webhooks::WebhookEventType::Status      |
+        webhooks::WebhookEventType::CheckSuite  => {
+            match state.staging {
+                Some(ref staging) if staging.sha == event.sha => check_staging(tsconfig, outbound, &client, staging),
+                _                                             => ()
+            }
+        }
+        _                                       => ()
+    }
+}
+
+//Drops the staged PR once CI kept quiet about it for "queue_timeout" seconds,
+//the queue would wait for it forever otherwise
+fn expire_staging(tsconfig: &Arc<Mutex<config::ConfigHandler>>, outbound: &outbound::OutboundQueue, client: &github::GithubClient) {
+
+    let staging = match QueueState::load(tsconfig) {
+        Ok(state) => match state.staging {
+            Some(staging) => staging,
+            None          => return
+        },
+        Err(err)  => {
+            thread_error!("{}", err);
+            return;
+        }
+    };
+    let timeout = queue_timeout(tsconfig);
+    if UTC::now().timestamp() - staging.started_at < timeout {
+        return;
+    }
+
+    thread_warn!("CI did not report on #{} within {} seconds", staging.entry.number, timeout);
+    if let Err(err) = clear_staging(tsconfig) {
+        thread_error!("{}", err);
+        return;
+    }
+    report(tsconfig, outbound, &staging.entry, Message::text(&format!("CI did not report on {} within {} seconds, took #{} out of the merge queue.",
+        short_sha(&staging.sha), timeout, staging.entry.number)));
+    advance(tsconfig, outbound, client);
+}
+
+//Fast-forwards the base branch once CI is green on the staging commit,
+//reports and moves on to the next PR when it is red
+fn check_staging(tsconfig: &Arc<Mutex<config::ConfigHandler>>, outbound: &outbound::OutboundQueue, client: &github::GithubClient, staging: &Staging) {
+
+    thread_trace!("queue.rs: check_staging(tsconfig, outbound, client, staging)");
+
+    let (repo, base) = branches(tsconfig);
+    let ci = match merge::ci(client, &repo, &staging.sha) {
+        Ok(ci)   => ci,
+        Err(err) => {
+            thread_error!("Failed to get the CI results of {}: {}", staging.sha, err);
+            return;
+        }
+    };
+    if ci.iter().any(|check| check.state == CheckState::Pending) {
+        thread_debug!("CI of #{} is still running", staging.entry.number);
+        return;
+    }
+
+    let failures: Vec<String> = ci.iter().filter(|check| check.state == CheckState::Failed).map(|check| check.text.clone()).collect();
+    let message = if !failures.is_empty() {
+        Message::text(&format!("#{} failed on top of {}, took it out of the merge queue:", staging.entry.number, response::inline_code(&base)))
+            .list(&failures)
+    } else {
+        match fast_forward(client, &repo, &base, staging) {
+            Ok(true)  => {
+                thread_info!("The merge queue merged #{}", staging.entry.number);
+                Message::text(&format!("Merged #{} into {} ({}), CI was green on top of it.", staging.entry.number, response::inline_code(&base), short_sha(&staging.sha)))
+            }
+            //The base branch moved, the PR is tested again on top of it
+            Ok(false) => {
+                thread_info!("{} moved while #{} was tested, testing it again", base, staging.entry.number);
+                requeue_front(tsconfig, staging);
+                advance(tsconfig, outbound, client);
+                return;
+            }
+            Err(err)  => Message::text(&format!("Failed to merge #{} into {}, took it out of the merge queue: {}", staging.entry.number, response::inline_code(&base), err))
+        }
+    };
+
+    if let Err(err) = clear_staging(tsconfig) {
+        thread_error!("{}", err);
+    }
+    report(tsconfig, outbound, &staging.entry, message);
+    advance(tsconfig, outbound, client);
+}
+
+//Stages the PR at the front of the queue, unless one is staged already.
+//PRs that cannot be staged, i.e because of conflicts, are reported and dropped.
+pub fn advance(tsconfig: &Arc<Mutex<config::ConfigHandler>>, outbound: &outbound::OutboundQueue, client: &github::GithubClient) {
+
+    thread_trace!("queue.rs: advance(tsconfig, outbound, client)");
+
+    let (repo, base) = branches(tsconfig);
+    let staging      = staging_branch(tsconfig);
+    loop {
+        let mut state = match QueueState::load(tsconfig) {
+            Ok(state) => state,
+            Err(err)  => {
+                thread_error!("{}", err);
+                return;
+            }
+        };
+        if state.staging.is_some() || state.entries.is_empty() {
+            return;
+        }
+
+        let entry = state.entries.remove(0);
+        match stage(client, &repo, &base, &staging, &entry) {
+            Ok((sha, base_sha)) => {
+                thread_info!("Testing #{} on {} as {}", entry.number, staging, sha);
+                state.staging = Some(Staging {
+                    entry:      entry.clone(),
+                    sha:        sha.clone(),
+                    base_sha:   base_sha,
+                    started_at: UTC::now().timestamp()
+                });
+                if let Err(err) = state.save(tsconfig) {
+                    thread_error!("{}", err);
+                }
+                report(tsconfig, outbound, &entry, Message::text(&format!("Testing #{} on top of {} in {} ({}).", entry.number,
+                    response::inline_code(&base), response::inline_code(&staging), short_sha(&sha))));
+                return;
+            }
+            Err(err)            => {
+                thread_warn!("Failed to stage #{}: {}", entry.number, err);
+                if let Err(err) = state.save(tsconfig) {
+                    thread_error!("{}", err);
+                    return;
+                }
+                report(tsconfig, outbound, &entry, Message::text(&format!("Took #{} out of the merge queue: {}", entry.number, err)));
+            }
+        }
+    }
+}
+
+//Resets the staging branch to the base branch and merges the approved commit into it.
+//Ok: (sha of the merge commit, sha of the base branch)
+fn stage(client: &github::GithubClient, repo: &str, base: &str, staging: &str, entry: &QueueEntry) -> Result<(String, String), String> {
+
+    let base_ref = try!(client.get(&format!("repos/{}/git/refs/heads/{}", repo, base)));
+    let base_sha = try!(base_ref.find_path(&["object", "sha"]).and_then(|sha| sha.as_str()).map(String::from)
+        .ok_or(format!("{} has no commit.", response::inline_code(base))));
+
+    let mut reset: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+    reset.insert(String::from("sha"),   serde_json::Value::String(base_sha.clone()));
+    reset.insert(String::from("force"), serde_json::Value::Bool(true));
+    let (status, _) = try!(client.request(Method::Patch, &format!("repos/{}/git/refs/heads/{}", repo, staging), Some(&try!(to_json_string(&reset)))));
+    if status / 100 != 2 {
+        //The staging branch does not exist yet
+        let mut create: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+        create.insert(String::from("ref"), serde_json::Value::String(format!("refs/heads/{}", staging)));
+        create.insert(String::from("sha"), serde_json::Value::String(base_sha.clone()));
+        try!(client.post(&format!("repos/{}/git/refs", repo), &try!(to_json_string(&create))));
+    }
+
+    let mut merge: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+    merge.insert(String::from("base"),           serde_json::Value::String(String::from(staging)));
+    merge.insert(String::from("head"),           serde_json::Value::String(entry.head_sha.clone()));
+    merge.insert(String::from("commit_message"), serde_json::Value::String(format!("Merge #{}: {}\n\nApproved-by: {}", entry.number, entry.title, entry.approved_by)));
+    let (status, value) = try!(client.request(Method::Post, &format!("repos/{}/merges", repo), Some(&try!(to_json_string(&merge)))));
+    match status {
+        201 => {
+            let sha = try!(value.find("sha").and_then(|sha| sha.as_str()).map(String::from).ok_or(String::from("Github did not give the merge commit.")));
+            Ok((sha, base_sha))
+        }
+        204 => Err(format!("it is merged into {} already.", response::inline_code(base))),
+        409 => Err(format!("it conflicts with {}.", response::inline_code(base))),
+        _   => Err(format!("merging it into {} failed with status {}.", response::inline_code(staging), status))
+    }
+}
+
+//Ok: false if the base branch moved since the staging, it cannot be fast-forwarded.
+//Other refusals, i.e by a branch protection, are errors.
+fn fast_forward(client: &github::GithubClient, repo: &str, base: &str, staging: &Staging) -> Result<bool, String> {
+    let mut update: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+    update.insert(String::from("sha"),   serde_json::Value::String(staging.sha.clone()));
+    update.insert(String::from("force"), serde_json::Value::Bool(false));
+    let (status, value) = try!(client.request(Method::Patch, &format!("repos/{}/git/refs/heads/{}", repo, base), Some(&try!(to_json_string(&update)))));
+    let message         = value.find("message").and_then(|message| message.as_str()).unwrap_or("");
+    match status {
+        200 => Ok(true),
+        422 => {
+            let base_ref = try!(client.get(&format!("repos/{}/git/refs/heads/{}", repo, base)));
+            match base_ref.find_path(&["object", "sha"]).and_then(|sha| sha.as_str()) {
+                Some(base_sha) if base_sha != staging.base_sha => Ok(false),
+                _                                              => Err(format!("updating {} was refused: {}", response::inline_code(base), message))
+            }
+        }
+        _   => Err(format!("updating {} failed with status {}: {}", response::inline_code(base), status, message))
+    }
+}
+
+//Ok: The entry taken out of the queue, staged or waiting, None if the PR was not queued
+pub fn remove(tsconfig: &Arc<Mutex<config::ConfigHandler>>, number: u64) -> Result<Option<QueueEntry>, String> {
+    let mut state   = try!(QueueState::load(tsconfig));
+    let mut removed = state.entries.iter().find(|entry| entry.number == number).cloned();
+    state.entries.retain(|entry| entry.number != number);
+    if state.staging.as_ref().map(|staging| staging.entry.number == number).unwrap_or(false) {
+        removed       = state.staging.take().map(|staging| staging.entry);
+    }
+    if removed.is_some() {
+        try!(state.save(tsconfig));
+    }
+    Ok(removed)
+}
+
+fn clear_staging(tsconfig: &Arc<Mutex<config::ConfigHandler>>) -> Result<(), String> {
+    let mut state = try!(QueueState::load(tsconfig));
+    state.staging = None;
+    state.save(tsconfig)
+}
+
+//Puts the staged PR back at the front of the queue
+fn requeue_front(tsconfig: &Arc<Mutex<config::ConfigHandler>>, staging: &Staging) {
+    let result = QueueState::load(tsconfig).and_then(|mut state| {
+        state.staging = None;
+        state.entries.insert(0, staging.entry.clone());
+        state.save(tsconfig)
+    });
+    if let Err(err) = result {
+        thread_error!("{}", err);
+    }
+}
+
+//Tells the user who approved the PR
+fn report(tsconfig: &Arc<Mutex<config::ConfigHandler>>, outbound: &outbound::OutboundQueue, entry: &QueueEntry, msg: Message) {
+    let mut event = webhooks::WebhookEvent::new();
+    event.number  = entry.number;
+    event.user    = entry.approved_by.clone();
+    commands::respond(tsconfig, outbound, event, msg);
+}
+
+//(repository, base branch), "queue_branch" of the config or "master"
+fn branches(tsconfig: &Arc<Mutex<config::ConfigHandler>>) -> (String, String) {
+    let mut config = tsconfig.lock().unwrap();
+    (config.get_string_required("config", "github_follow_repo"), config.get_string_or("config", "queue_branch", "master"))
+}
+
+//"staging_branch" of the config or "staging"
+fn staging_branch(tsconfig: &Arc<Mutex<config::ConfigHandler>>) -> String {
+    tsconfig.lock().unwrap().get_string_or("config", "staging_branch", "staging")
+}
+
+//"queue_timeout" of the config in seconds or 4 hours
+fn queue_timeout(tsconfig: &Arc<Mutex<config::ConfigHandler>>) -> i64 {
+    tsconfig.lock().unwrap().get_string_or("config", "queue_timeout", "14400").parse().unwrap_or(14400)
+}
+
+fn entry_row(position: usize, entry: &QueueEntry, status: &str) -> Vec<String> {
+    vec![
+        position.to_string(),
+        format!("#{} {}", entry.number, entry.title),
+        entry.approved_by.clone(),
+        short_sha(&entry.head_sha),
+        String::from(status)
+    ]
+}
+
+fn short_sha(sha: &str) -> String {
+    response::inline_code(&sha.chars().take(7).collect::<String>())
+}
+
+fn to_json_string(object: &BTreeMap<String, serde_json::Value>) -> Result<String, String> {
+    serde_json::to_string(object).map_err(|err| format!("Failed to serialize the request: {}", err))
+}
@@ -0,0 +1,47 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+//Reactions to the comment a command came from: :eyes: while the bot works
+//on it, then :+1: or :confused: depending on how it went.
+
+use github;
+use webhooks;
+
+include!("logger_macros.rs");
+
+
+////////////////////////////////////////////////////////////
+//                          Funcs                         //
+////////////////////////////////////////////////////////////
+
+//Reacts to the comment of the event, returns the reaction id.
+//content: "+1", "-1", "laugh", "confused", "heart", "hooray" or "eyes"
+pub fn add(client: &github::GithubClient, repo: &str, event: &webhooks::WebhookEvent, content: &str) -> Result<u64, String> {
+
+    thread_trace!("reactions.rs: add(client, \"{}\", event, \"{}\")", repo, content);
+
+    let endpoint = try!(reactions_endpoint(repo, event));
+    let reaction = try!(client.post(&endpoint, &format!("{{\"content\": \"{}\"}}", content)));
+    match reaction.find("id").and_then(|id| id.as_u64()) {
+        Some(id) => Ok(id),
+        None     => Err(format!("The reaction created at {} has no id.", endpoint))
+    }
+}
+
+pub fn remove(client: &github::GithubClient, repo: &str, event: &webhooks::WebhookEvent, reaction_id: u64) -> Result<(), String> {
+
+    thread_trace!("reactions.rs: remove(client, \"{}\", event, {})", repo, reaction_id);
+
+    let endpoint = try!(reactions_endpoint(repo, event));
+    try!(client.delete(&format!("{}/{}", endpoint, reaction_id)));
+    Ok(())
+}
+
+//Issue comments and PR review comments have reactions endpoints of their own
+fn reactions_endpoint(repo: &str, event: &webhooks::WebhookEvent) -> Result<String, String> {
+    match event.event_type {
+        webhooks::WebhookEventType::IssueComment       => Ok(format!("repos/{}/issues/comments/{}/reactions", repo, event.comment_id)),
+        webhooks::WebhookEventType::PullRequestComment => Ok(format!("repos/{}/pulls/comments/{}/reactions", repo, event.comment_id)),
+        _                                              => Err(format!("{:?} events have no comment to react to.", event.event_type))
+    }
+}
@@ -0,0 +1,343 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::iter;
+
+extern crate serde_json;
+
+include!("logger_macros.rs");
+
+
+////////////////////////////////////////////////////////////
+//                        Message                         //
+////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug, PartialEq)]
+enum Block {
+    Paragraph(String),
+    Markdown(String)
+}
+
+///Markdown body of a bot comment, built block by block:
+///
+///    Message::text("Build results:")
+///        .table(vec!["Toolchain", "Result"], rows)
+///        .details("Log", Message::new().code_block("", &log))
+///
+///Tagging a message with sticky() makes the bot edit its previous comment
+///with the same tag on the issue instead of posting a new one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    blocks: Vec<Block>,
+    sticky: Option<String>
+}
+
+impl Message {
+
+    pub fn new() -> Message {
+        Message {
+            blocks: Vec::new(),
+            sticky: None
+        }
+    }
+
+    //Message made of a single paragraph
+    pub fn text(text: &str) -> Message {
+        Message::new().paragraph(text)
+    }
+
+    pub fn paragraph(mut self, text: &str) -> Message {
+        self.blocks.push(Block::Paragraph(String::from(text.trim())));
+        self
+    }
+
+    //Bullet list, one item per entry
+    pub fn list(mut self, items: &Vec<String>) -> Message {
+        let lines: Vec<String> = items.iter().map(|item| format!("* {}", item.replace("\n", " "))).collect();
+        self.blocks.push(Block::Markdown(lines.join("\n")));
+        self
+    }
+
+    //Fenced code block, the fence is made longer than any backtick run in the code
+    pub fn code_block(mut self, language: &str, code: &str) -> Message {
+        let fence = backticks(cmp::max(3, longest_backtick_run(code) + 1));
+        self.blocks.push(Block::Markdown(format!("{}{}\n{}\n{}", fence, language, code.trim_right_matches('\n'), fence)));
+        self
+    }
+
+    pub fn table(mut self, header: Vec<&str>, rows: Vec<Vec<String>>) -> Message {
+        let mut lines: Vec<String> = Vec::new();
+        let header_cells: Vec<String> = header.iter().map(|cell| escape_table_cell(cell)).collect();
+        lines.push(format!("| {} |", header_cells.join(" | ")));
+        lines.push(format!("|{}", header.iter().map(|_| " --- |").collect::<Vec<&str>>().join("")));
+        for row in rows {
+            let cells: Vec<String> = row.iter().map(|cell| escape_table_cell(cell)).collect();
+            lines.push(format!("| {} |", cells.join(" | ")));
+        }
+        self.blocks.push(Block::Markdown(lines.join("\n")));
+        self
+    }
+
+    //Collapsible section, GitHub renders <details> folded by default
+    pub fn details(mut self, summary: &str, content: Message) -> Message {
+        self.blocks.push(Block::Markdown(format!("<details>\n<summary>{}</summary>\n\n{}\n\n</details>", escape_html(summary), content.to_markdown())));
+        self
+    }
+
+    pub fn append(mut self, other: Message) -> Message {
+        self.blocks.extend(other.blocks);
+        if self.sticky.is_none() {
+            self.sticky = other.sticky;
+        }
+        self
+    }
+
+    pub fn sticky(mut self, tag: &str) -> Message {
+        self.sticky = Some(String::from(tag));
+        self
+    }
+
+    pub fn sticky_tag(&self) -> Option<String> {
+        self.sticky.clone()
+    }
+
+    //Addresses the message to a user: "@user" in front of the first paragraph
+    pub fn mention(mut self, user: &str) -> Message {
+        let mention = format!("@{}", user);
+        let first   = match self.blocks.first() {
+            Some(&Block::Paragraph(ref text)) => Some(text.clone()),
+            _                                 => None
+        };
+        match first {
+            Some(text) => {self.blocks[0] = Block::Paragraph(format!("{} {}", mention, text));}
+            None       => {self.blocks.insert(0, Block::Paragraph(mention));}
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let blocks: Vec<String> = self.blocks.iter().map(|block| {
+            match *block {
+                Block::Paragraph(ref text) => text.clone(),
+                Block::Markdown(ref text)  => text.clone()
+            }
+        }).collect();
+        blocks.join("\n\n")
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                          Funcs                         //
+////////////////////////////////////////////////////////////
+
+//Inline code span, i.e for user input echoed back in a reply
+pub fn inline_code(text: &str) -> String {
+    let fence = backticks(longest_backtick_run(text) + 1);
+    if text.starts_with('`') || text.ends_with('`') {
+        return format!("{} {} {}", fence, text, fence)
+    }
+    format!("{}{}{}", fence, text, fence)
+}
+
+//Request body to create or edit a comment: {"body": "..."}
+pub fn comment_json(body: &str) -> Result<String, String> {
+
+    thread_trace!("response.rs: comment_json(body)");
+
+    let mut object: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+    object.insert(String::from("body"), serde_json::Value::String(String::from(body)));
+    match serde_json::to_string(&serde_json::Value::Object(object)) {
+        Ok(json) => Ok(json),
+        Err(err) => Err(format!("Failed to serialize the comment: {}", err.description()))
+    }
+}
+
+fn backticks(count: usize) -> String {
+    iter::repeat("`").take(count).collect()
+}
+
+fn longest_backtick_run(text: &str) -> usize {
+    let mut longest = 0;
+    let mut current = 0;
+    for c in text.chars() {
+        if c == '`' {
+            current += 1;
+            longest  = cmp::max(longest, current);
+        } else {
+            current = 0;
+        }
+    }
+    longest
+}
+
+fn escape_table_cell(text: &str) -> String {
+    text.replace("|", "\\|").replace("\r", "").replace("\n", "<br>")
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;")
+}
+
+
+////////////////////////////////////////////////////////////
+//                       Splitting                        //
+////////////////////////////////////////////////////////////
+
+//Github rejects comment bodies longer than this (in characters)
+pub const MAX_COMMENT_LENGTH: usize = 65536;
+
+//Splits markdown into parts of at most `limit` characters. Parts are cut
+//between blocks (blank lines outside of code fences and <details>); blocks
+//that are too long on their own are cut between lines, closing and
+//reopening the code fence they are in so that every part renders.
+pub fn split_markdown(markdown: &str, limit: usize) -> Vec<String> {
+
+    thread_trace!("response.rs: split_markdown(markdown, {})", limit);
+
+    let mut parts:   Vec<String> = Vec::new();
+    let mut current: String      = String::new();
+
+    for block in markdown_blocks(markdown) {
+        let pieces = if char_len(&block) > limit {split_lines(&block, limit)} else {vec![block]};
+        for piece in pieces {
+            if !current.is_empty() && char_len(&current) + 2 + char_len(&piece) > limit {
+                parts.push(current);
+                current = String::new();
+            }
+            if !current.is_empty() {
+                current.push_str("\n\n");
+            }
+            current.push_str(&piece);
+        }
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+
+    thread_trace!("Return {} part(s)", parts.len());
+    parts
+}
+
+//Beginning of the markdown that fits in `limit` characters, cut on the same boundaries as split_markdown()
+pub fn truncate_markdown(markdown: &str, limit: usize) -> String {
+    match split_markdown(markdown, limit).into_iter().next() {
+        Some(part) => part,
+        None       => String::new()
+    }
+}
+
+//Blank line separated blocks, a code fence or a <details> section is never split
+fn markdown_blocks(markdown: &str) -> Vec<String> {
+
+    let mut blocks:  Vec<String>    = Vec::new();
+    let mut current: Vec<&str>      = Vec::new();
+    let mut fence:   Option<String> = None;
+    let mut details: usize          = 0;
+
+    for line in markdown.lines() {
+        if line.trim().is_empty() && fence.is_none() && details == 0 {
+            if !current.is_empty() {
+                blocks.push(current.join("\n"));
+                current.clear();
+            }
+            continue;
+        }
+        current.push(line);
+
+        fence = next_fence_state(fence, line);
+        if fence.is_none() {
+            details += line.matches("<details>").count();
+            details  = details.saturating_sub(line.matches("</details>").count());
+        }
+    }
+    if !current.is_empty() {
+        blocks.push(current.join("\n"));
+    }
+    blocks
+}
+
+fn split_lines(block: &str, limit: usize) -> Vec<String> {
+
+    let mut chunks:  Vec<String>    = Vec::new();
+    let mut current: String         = String::new();
+    let mut fence:   Option<String> = None;
+
+    for line in block.lines() {
+        //Leave room for reopening and closing a fence around a hard split line
+        for piece in split_chars(line, cmp::max(limit / 2, 1)) {
+            let closing = match fence {
+                Some(ref open) => char_len(&fence_marker(open)) + 1,
+                None           => 0
+            };
+            if !current.is_empty() && char_len(&current) + 1 + char_len(&piece) + closing > limit {
+                if let Some(ref open) = fence {
+                    current.push('\n');
+                    current.push_str(&fence_marker(open));
+                }
+                chunks.push(current);
+                current = match fence {
+                    Some(ref open) => open.clone(),
+                    None           => String::new()
+                };
+            }
+            if !current.is_empty() {
+                current.push('\n');
+            }
+            current.push_str(&piece);
+        }
+        fence = next_fence_state(fence, line);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+//Tracks whether a line opens or closes a code fence, Some holds the opening line
+fn next_fence_state(fence: Option<String>, line: &str) -> Option<String> {
+    let trimmed = line.trim();
+    match fence {
+        Some(open) => {
+            let marker = fence_marker(&open);
+            if trimmed.starts_with(&marker[..]) && trimmed.chars().all(|c| c == marker.chars().next().unwrap()) {
+                None
+            } else {
+                Some(open)
+            }
+        }
+        None       => {
+            if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                Some(String::from(line))
+            } else {
+                None
+            }
+        }
+    }
+}
+
+//"````rust" -> "````"
+fn fence_marker(open: &str) -> String {
+    let trimmed = open.trim();
+    let first   = trimmed.chars().next().unwrap_or('`');
+    trimmed.chars().take_while(|c| *c == first).collect()
+}
+
+fn split_chars(line: &str, limit: usize) -> Vec<String> {
+    if char_len(line) <= limit {
+        return vec![String::from(line)]
+    }
+    let chars: Vec<char> = line.chars().collect();
+    chars.chunks(limit).map(|chunk| chunk.iter().cloned().collect()).collect()
+}
+
+fn char_len(text: &str) -> usize {
+    text.chars().count()
+}
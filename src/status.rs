@@ -0,0 +1,112 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+//"status": what the bot knows about itself, its health and a summary of its
+//config. Only the settings listed here are shown, the config holds tokens,
+//secrets and addresses that have no business in a comment.
+
+use std::cmp;
+
+extern crate chrono;
+use self::chrono::*;
+
+use log;
+
+use commands;
+use permissions::Role;
+use queue;
+use response;
+use response::Message;
+
+include!("logger_macros.rs");
+
+
+////////////////////////////////////////////////////////////
+//                     StatusCommand                      //
+////////////////////////////////////////////////////////////
+
+pub struct StatusCommand;
+
+impl StatusCommand {
+    pub fn command() -> commands::Command {
+        commands::Command::new(false, Role::Owner, StatusCommand)
+            .whitelisted()
+            .summary("Reports the health of the bot and a summary of its configuration.")
+            .usage("status")
+            .example("status")
+    }
+}
+
+impl commands::BotCommand for StatusCommand {
+    fn run(&self, context: &mut commands::CommandContext) -> Result<Message, String> {
+
+        thread_trace!("status.rs: StatusCommand::run(&self, context)");
+
+        let repo     = context.config.get_string_required("config", "github_follow_repo");
+        let hook_ids = context.config.get_string_or("state", "github_webhook_id", "");
+        let jobs     = context.jobs.running().len();
+        let queued   = match queue::QueueState::load(&context.tsconfig) {
+            Ok(state) => format!("{} pull request(s)", state.entries.len() + if state.staging.is_some() {1} else {0}),
+            Err(err)  => {
+                thread_warn!("{}", err);
+                String::from("Unknown")
+            }
+        };
+
+        let rows = vec![
+            vec![String::from("Version"),            String::from(env!("CARGO_PKG_VERSION"))],
+            vec![String::from("Uptime"),             format_duration(UTC::now().timestamp() - context.started_at)],
+            vec![String::from("Repositories"),       response::inline_code(&repo)],
+            vec![String::from("Webhook ids"),        if hook_ids.is_empty() {String::from("None registered")} else {hook_ids}],
+            vec![String::from("Merge queue"),        queued],
+            vec![String::from("Replies waiting"),    context.outbound.pending().to_string()],
+            vec![String::from("Workers"),            format!("1 command worker, 1 delivery worker, {} job(s)", jobs)],
+            vec![String::from("Github rate limit"),  rate_limit(context)],
+            vec![String::from("Log directory"),      response::inline_code(&context.config.log_dir.display().to_string())],
+            vec![String::from("Log level"),          format!("{}", log::max_log_level())],
+            vec![String::from("Dry run"),            String::from(if context.config.is_dry_run() {"Yes"} else {"No"})]
+        ];
+
+        Ok(Message::text("All systems go.")
+            .table(vec!["Item", "Value"], rows))
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                          Funcs                         //
+////////////////////////////////////////////////////////////
+
+//Remaining calls of the bot's token, i.e "4990/5000, resets at 12:00:00 UTC"
+fn rate_limit(context: &commands::CommandContext) -> String {
+    let core = match context.github.get("rate_limit") {
+        Ok(value) => match value.find("resources").and_then(|resources| resources.find("core")) {
+            Some(core) => core.clone(),
+            None       => return String::from("Unknown")
+        },
+        Err(err)  => {
+            thread_warn!("Failed to get the rate limit: {}", err);
+            return String::from("Unknown")
+        }
+    };
+
+    match (core.find("remaining").and_then(|remaining| remaining.as_u64()), core.find("limit").and_then(|limit| limit.as_u64()), core.find("reset").and_then(|reset| reset.as_i64())) {
+        (Some(remaining), Some(limit), Some(reset)) => format!("{}/{}, resets at {}", remaining, limit, UTC.timestamp(reset, 0).format("%H:%M:%S UTC")),
+        _                                           => String::from("Unknown")
+    }
+}
+
+//"1d 2h 3m 4s", the leading zero units are left out
+fn format_duration(seconds: i64) -> String {
+    let units = [("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60), ("s", 1)];
+    let mut left               = cmp::max(0, seconds);
+    let mut parts: Vec<String> = Vec::new();
+    for &(unit, length) in units.iter() {
+        let count = left / length;
+        left     -= count * length;
+        if count > 0 || !parts.is_empty() || unit == "s" {
+            parts.push(format!("{}{}", count, unit));
+        }
+    }
+    parts.join(" ")
+}
@@ -0,0 +1,66 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+//Sticky comments: one bot comment per (repo, issue, tag) which is edited in
+//place. The comment is found again through a hidden HTML marker in its body.
+
+use github;
+use response;
+use webhooks;
+
+include!("logger_macros.rs");
+
+
+////////////////////////////////////////////////////////////
+//                          Funcs                         //
+////////////////////////////////////////////////////////////
+
+//Hidden marker identifying the sticky comment of a tag, i.e <!-- hunter-bot sticky: tests -->
+pub fn marker(tag: &str) -> String {
+    let tag: String = tag.chars().filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_' || *c == '.').collect();
+    format!("<!-- hunter-bot sticky: {} -->", tag)
+}
+
+//Markdown of a sticky comment, the marker goes last so that it does not affect the rendering
+pub fn with_marker(markdown: &str, tag: &str) -> String {
+    format!("{}\n\n{}", markdown, marker(tag))
+}
+
+///Edits the comment made by bot_name that carries the marker of tag, creates it if there is none.
+///comments_endpoint: repos/{repo}/issues/{number}/comments
+///body:              JSON body of the comment, see response::comment_json()
+pub fn upsert(client: &github::GithubClient, bot_name: &str, comments_endpoint: &str, tag: &str, body: &str) -> Result<(), String> {
+
+    thread_trace!("sticky.rs: upsert(client, \"{}\", {}, \"{}\", body)", bot_name, comments_endpoint, tag);
+
+    let marker   = marker(tag);
+    let comments = try!(client.get_all(comments_endpoint));
+
+    thread_trace!("  Look for an existing comment");
+    for comment in comments {
+        let comment_object = try!(webhooks::extract_json_object(&comment));
+        let user_object    = try!(webhooks::extract_json_object_named(&comment_object, "user"));
+        let login          = try!(webhooks::extract_json_string(&user_object, "login"));
+        let comment_body   = try!(webhooks::extract_json_string(&comment_object, "body"));
+
+        if login == bot_name && comment_body.contains(&marker[..]) {
+            let id   = try!(webhooks::extract_json_u64(&comment_object, "id"));
+            let repo = try!(repo_endpoint(comments_endpoint));
+            thread_debug!("Updating sticky comment {} ({})", id, tag);
+            try!(client.patch(&format!("{}/issues/comments/{}", repo, id), body));
+            return Ok(())
+        }
+    }
+
+    thread_debug!("Creating sticky comment ({})", tag);
+    try!(client.post(comments_endpoint, body));
+    Ok(())
+}
+
+//repos/{owner}/{repo}/issues/{number}/comments -> repos/{owner}/{repo}
+fn repo_endpoint(comments_endpoint: &str) -> Result<String, String> {
+    match comments_endpoint.find("/issues/") {
+        Some(index) => Ok(String::from(&comments_endpoint[..index])),
+        None        => Err(format!("{} is not an issue comments endpoint.", response::inline_code(comments_endpoint)))
+    }
+}
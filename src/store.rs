@@ -0,0 +1,143 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+//State the bot keeps between restarts, as files in "data_dir": whole JSON
+//documents that are rewritten on every save, and append-only logs of one
+//JSON object per line.
+
+use std::error::Error;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+extern crate serde_json;
+
+use config;
+
+include!("logger_macros.rs");
+
+
+////////////////////////////////////////////////////////////
+//                         Store                          //
+////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug)]
+pub struct Store {
+    data_dir: PathBuf
+}
+
+impl Store {
+
+    pub fn new(tsconfig: &Arc<Mutex<config::ConfigHandler>>) -> Store {
+        Store::from_config(&mut tsconfig.lock().unwrap())
+    }
+
+    //Same as new() for callers already holding the config
+    pub fn from_config(config: &mut config::ConfigHandler) -> Store {
+        Store {
+            data_dir: PathBuf::from(config.get_string_or("config", "data_dir", "./"))
+        }
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        let mut path = self.data_dir.clone();
+        path.push(name);
+        path
+    }
+
+    //Ok(None) if the document was never saved
+    pub fn load(&self, name: &str) -> Result<Option<serde_json::Value>, String> {
+
+        thread_trace!("store.rs: Store::load(&self, \"{}\")", name);
+
+        let data = match try!(self.read(name)) {
+            Some(data) => data,
+            None       => return Ok(None)
+        };
+        if data.trim().is_empty() {
+            return Ok(None)
+        }
+        match serde_json::from_str(&data[..]) {
+            Ok(value) => Ok(Some(value)),
+            Err(err)  => Err(format!("Failed to parse \"{}\": {}", self.path(name).display(), err))
+        }
+    }
+
+    //Replaces the document, written to a temporary file first so that a crash cannot leave half of it
+    pub fn save(&self, name: &str, value: &serde_json::Value) -> Result<(), String> {
+
+        thread_trace!("store.rs: Store::save(&self, \"{}\", value)", name);
+
+        let data = match serde_json::to_string(value) {
+            Ok(data) => data,
+            Err(err) => return Err(format!("Failed to serialize \"{}\": {}", name, err.description()))
+        };
+
+        try!(self.create_data_dir());
+        let path     = self.path(name);
+        let tmp_path = self.path(&format!("{}.tmp", name));
+        match File::create(&tmp_path).and_then(|mut file| file.write_all(data.as_bytes())).and_then(|_| fs::rename(&tmp_path, &path)) {
+            Ok(())   => Ok(()),
+            Err(err) => Err(format!("Failed to write \"{}\": {}", path.display(), err.description()))
+        }
+    }
+
+    //Adds an entry at the end of a log
+    pub fn append(&self, name: &str, value: &serde_json::Value) -> Result<(), String> {
+
+        thread_trace!("store.rs: Store::append(&self, \"{}\", value)", name);
+
+        let line = match serde_json::to_string(value) {
+            Ok(line) => line,
+            Err(err) => return Err(format!("Failed to serialize an entry of \"{}\": {}", name, err.description()))
+        };
+
+        try!(self.create_data_dir());
+        let path = self.path(name);
+        match OpenOptions::new().append(true).create(true).open(&path).and_then(|mut file| file.write_all(format!("{}\n", line).as_bytes())) {
+            Ok(())   => Ok(()),
+            Err(err) => Err(format!("Failed to write \"{}\": {}", path.display(), err.description()))
+        }
+    }
+
+    //Entries of a log, oldest first
+    pub fn entries(&self, name: &str) -> Result<Vec<serde_json::Value>, String> {
+
+        thread_trace!("store.rs: Store::entries(&self, \"{}\")", name);
+
+        let data = match try!(self.read(name)) {
+            Some(data) => data,
+            None       => return Ok(Vec::new())
+        };
+
+        let mut entries: Vec<serde_json::Value> = Vec::new();
+        for line in data.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str(line) {
+                Ok(value) => entries.push(value),
+                Err(err)  => return Err(format!("Failed to parse an entry of \"{}\": {}", self.path(name).display(), err))
+            }
+        }
+        Ok(entries)
+    }
+
+    fn read(&self, name: &str) -> Result<Option<String>, String> {
+        let path = self.path(name);
+        if !path.exists() {
+            return Ok(None)
+        }
+        let mut data = String::new();
+        match File::open(&path).and_then(|mut file| file.read_to_string(&mut data)) {
+            Ok(_)    => Ok(Some(data)),
+            Err(err) => Err(format!("Failed to read \"{}\": {}", path.display(), err.description()))
+        }
+    }
+
+    fn create_data_dir(&self) -> Result<(), String> {
+        match fs::create_dir_all(&self.data_dir) {
+            Ok(())   => Ok(()),
+            Err(err) => Err(format!("Failed to create the data directory \"{}\": {}", self.data_dir.display(), err.description()))
+        }
+    }
+}
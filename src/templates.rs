@@ -0,0 +1,195 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+//Texts of the replies the bot makes on its own, as opposed to the output of
+//commands. Each one can be replaced in the "templates" section of the
+//config, i.e `not_allowed = "Only {role} can do that, {user}."`.
+//Placeholders are written {name}, literal braces {{ and }}. The {command}
+//the user wrote is filled in as inline code, the template does not quote it.
+
+use std::collections::BTreeMap;
+
+use config;
+use response;
+
+include!("logger_macros.rs");
+
+pub const DOCS_URL: &'static str = "https://hunterbot.readthedocs.io";
+
+//Placeholders every template can use
+const COMMON_PLACEHOLDERS: &'static [&'static str] = &["user", "command", "docs_url"];
+
+//Placeholders whose value is text of the user, rendered as inline code
+const CODE_PLACEHOLDERS: &'static [&'static str] = &["command"];
+
+//Name, default text and the placeholders it can use on top of the common ones
+const DEFAULTS: &'static [(&'static str, &'static str, &'static [&'static str])] = &[
+    ("unparsable",             "Sorry, I could not understand that command. {error}\n\nArguments containing spaces can be quoted, i.e `test Boost --toolchain \"gcc 7\"`.", &["error"]),
+    ("empty_command",          "Yes? Please visit [{docs_url}]({docs_url}) for available commands.", &[]),
+    ("not_found",              "Sorry the command {command} was not found.", &[]),
+    ("suggestions",            "Did you mean {suggestions}?", &["suggestions"]),
+    ("available_commands",     "Commands you can use: {commands}. Say `help <command>` for more details.", &["commands"]),
+    ("not_allowed",            "Sorry! You need to be {role} to run that command.", &["role"]),
+    ("please_required",        "Whats the magic word?\n\nSay `please` within {minutes} minute(s) and I will run it.", &["minutes"]),
+    ("please_not_required",    "You didn't need to say please but thanks anyways :smiley:\n\nOhh and:", &[]),
+    ("please_accepted",        "Thanks! Running {command}:", &[]),
+    ("please_nothing_pending", "You're welcome, but there is no command of yours waiting for a please here.", &[]),
+    ("command_failed",         "An error occurred while executing the command: {error}", &["error"]),
+    ("help_footer",            "Say `help <command>` for more details about a command, or visit [{docs_url}]({docs_url}).", &[])
+];
+
+
+////////////////////////////////////////////////////////////
+//                       Templates                        //
+////////////////////////////////////////////////////////////
+
+/// Templates
+/// texts:    Text of every template, the defaults with the ones of the config on top
+/// docs_url: Link to the documentation ("docs_url" in the config), the {docs_url} placeholder
+#[derive(Clone, Debug)]
+pub struct Templates {
+    texts:    BTreeMap<String, String>,
+    docs_url: String
+}
+
+impl Templates {
+
+    //Built-in templates only
+    pub fn new() -> Templates {
+        Templates {
+            texts:    DEFAULTS.iter().map(|&(name, text, _)| (String::from(name), String::from(text))).collect(),
+            docs_url: String::from(DOCS_URL)
+        }
+    }
+
+    //Err if the config has a template that does not exist, is not a string
+    //or uses a placeholder it cannot use
+    pub fn from_config(config: &mut config::ConfigHandler) -> Result<Templates, String> {
+
+        thread_trace!("templates.rs: Templates::from_config(config)");
+
+        let mut templates = Templates::new();
+        templates.docs_url = config.get_string_or("config", "docs_url", DOCS_URL);
+
+        let section = match config.toml_data.get("templates") {
+            Some(section) => try!(section.as_table().ok_or(String::from("\"[templates]\" of the config is not a section."))).clone(),
+            None          => return Ok(templates)
+        };
+
+        for (name, value) in section.iter() {
+            let placeholders = match DEFAULTS.iter().find(|&&(default_name, _, _)| *name == default_name) {
+                Some(&(_, _, placeholders)) => placeholders,
+                None                        => return Err(format!("There is no template named \"{}\", the templates are: {}.", name, DEFAULTS.iter().map(|&(name, _, _)| name).collect::<Vec<&str>>().join(", ")))
+            };
+            let text = try!(value.as_str().ok_or(format!("The template \"{}\" does not represent a string.", name)));
+
+            for piece in try!(parse(text).map_err(|err| format!("The template \"{}\" is invalid: {}", name, err))) {
+                if let Piece::Placeholder(placeholder) = piece {
+                    if !COMMON_PLACEHOLDERS.iter().chain(placeholders.iter()).any(|known| *known == placeholder) {
+                        return Err(format!("The template \"{}\" cannot use {{{}}}, it can use: {}.", name, placeholder,
+                            COMMON_PLACEHOLDERS.iter().chain(placeholders.iter()).map(|placeholder| format!("{{{}}}", placeholder)).collect::<Vec<String>>().join(", ")))
+                    }
+                }
+            }
+            templates.texts.insert(name.clone(), String::from(text));
+        }
+
+        thread_debug!("Templates set in the config: {:?}", section.keys().collect::<Vec<&String>>());
+        Ok(templates)
+    }
+
+    //Fills in a template, placeholders without a value are left empty.
+    //values: (placeholder, value), {docs_url} is always available
+    pub fn render(&self, name: &str, values: &[(&str, &str)]) -> String {
+
+        thread_trace!("templates.rs: Templates::render(&self, \"{}\", values)", name);
+
+        let text = match self.texts.get(name) {
+            Some(text) => text,
+            None       => {thread_crash!("There is no template named \"{}\".", name);}
+        };
+
+        //Templates were validated when loaded, the defaults are known to be valid
+        let pieces = match parse(text) {
+            Ok(pieces) => pieces,
+            Err(err)   => {thread_crash!("The template \"{}\" is invalid: {}", name, err);}
+        };
+
+        let mut rendered = String::new();
+        for piece in pieces {
+            match piece {
+                Piece::Text(text)               => rendered.push_str(&text),
+                Piece::Placeholder(placeholder) => {
+                    if placeholder == "docs_url" {
+                        rendered.push_str(&self.docs_url);
+                    } else if let Some(&(_, value)) = values.iter().find(|&&(key, _)| key == placeholder) {
+                        if CODE_PLACEHOLDERS.contains(&&placeholder[..]) {
+                            rendered.push_str(&response::inline_code(value));
+                        } else {
+                            rendered.push_str(value);
+                        }
+                    }
+                }
+            }
+        }
+        rendered
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                          Funcs                         //
+////////////////////////////////////////////////////////////
+
+enum Piece {
+    Text(String),
+    Placeholder(String)
+}
+
+fn parse(text: &str) -> Result<Vec<Piece>, String> {
+
+    let mut pieces: Vec<Piece> = Vec::new();
+    let mut literal            = String::new();
+    let mut chars              = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{'                               => {
+                let mut placeholder = String::new();
+                let mut closed      = false;
+                while let Some(c) = chars.next() {
+                    if c == '}' {
+                        closed = true;
+                        break;
+                    }
+                    placeholder.push(c);
+                }
+                if !closed {
+                    return Err(format!("{{{} is not closed, use {{{{ for a literal brace.", placeholder))
+                }
+                if placeholder.is_empty() || !placeholder.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                    return Err(format!("{{{}}} is not a valid placeholder.", placeholder))
+                }
+                if !literal.is_empty() {
+                    pieces.push(Piece::Text(literal.clone()));
+                    literal.clear();
+                }
+                pieces.push(Piece::Placeholder(placeholder));
+            }
+            '}'                               => return Err(String::from("} is not opened, use }} for a literal brace.")),
+            _                                 => literal.push(c)
+        }
+    }
+    if !literal.is_empty() {
+        pieces.push(Piece::Text(literal));
+    }
+    Ok(pieces)
+}
@@ -0,0 +1,513 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+//Test support: a local fake of the Github API endpoints the bot talks to,
+//plus helpers to configure the bot against it and to send it signed webhooks.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::sync::mpsc::Sender;
+
+extern crate hyper;
+use hyper::Client;
+use hyper::header::Headers;
+use hyper::server::Listening;
+use hyper::status::StatusCode;
+
+extern crate iron;
+use self::iron::middleware;
+use self::iron::prelude::*;
+
+extern crate serde_json;
+
+use commands;
+use config;
+use outbound;
+use permissions::Role;
+use response;
+use webhooks;
+
+include!("logger_macros.rs");
+
+pub const TEST_BOT_NAME:       &'static str = "hunter-bot";
+pub const TEST_OWNER_NAME:     &'static str = "ruslo";
+pub const TEST_FOLLOW_REPO:    &'static str = "hunter-packages/hunter";
+pub const TEST_WEBHOOK_SECRET: &'static str = "1234567890";
+pub const TEST_DELIVERY_ID:    &'static str = "72d3162e-cc78-11e3-81ab-4c9367dc0958";
+
+//Combined status of a commit Travis passed, commits have no status by default
+pub const GREEN_STATUS:        &'static str = "{\"state\": \"success\", \"statuses\": [{\"context\": \"travis\", \"state\": \"success\"}]}";
+
+static TEMP_FILE_COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+
+
+////////////////////////////////////////////////////////////
+//                    RecordedRequest                     //
+////////////////////////////////////////////////////////////
+
+/// RecordedRequest
+/// method: HTTP method (GET, POST, ...)
+/// path:   Path without the query, i.e /repos/{owner}/{repo}/hooks
+/// query:  Raw query string, empty if none
+/// body:   Raw request body
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path:   String,
+    pub query:  String,
+    pub body:   String
+}
+
+impl RecordedRequest {
+    pub fn body_json(&self) -> serde_json::Value {
+        match serde_json::from_str(&self.body[..]) {
+            Ok(value) => value,
+            Err(err)  => panic!("Request body of {} {} is not valid JSON ({}): {}", self.method, self.path, err, self.body)
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                      FakeGithub                        //
+////////////////////////////////////////////////////////////
+
+struct FakeComment {
+    id:    u64,
+    issue: String,
+    user:  String,
+    body:  String
+}
+
+struct FakeGithubState {
+    requests:  Vec<RecordedRequest>,
+    responses: BTreeMap<String, (u16, String)>,
+    failures:  BTreeMap<String, usize>,
+    comments:  Vec<FakeComment>,
+    next_id:   u64
+}
+
+struct FakeGithubHandler {
+    state: Arc<Mutex<FakeGithubState>>
+}
+
+pub struct FakeGithub {
+    listening: Listening,
+    state:     Arc<Mutex<FakeGithubState>>
+}
+
+impl FakeGithub {
+
+    //Starts the fake API on a random local port
+    pub fn start() -> FakeGithub {
+        let state = Arc::new(Mutex::new(FakeGithubState {
+            requests:  Vec::new(),
+            responses: BTreeMap::new(),
+            failures:  BTreeMap::new(),
+            comments:  Vec::new(),
+            next_id:   1000
+        }));
+        let handler   = FakeGithubHandler{state: state.clone()};
+        let listening = match Iron::new(handler).http("127.0.0.1:0") {
+            Ok(listening) => listening,
+            Err(err)      => panic!("Failed to start the fake Github API: {}", err)
+        };
+        FakeGithub {
+            listening: listening,
+            state:     state
+        }
+    }
+
+    //Value to use for "github_api_url" in the config
+    pub fn url(&self) -> String {
+        format!("http://{}", self.listening.socket)
+    }
+
+    //Overrides the response for "METHOD /path", takes precedence over the built-in endpoints
+    pub fn respond_with(&self, method: &str, path: &str, status: u16, body: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.responses.insert(format!("{} {}", method, path), (status, String::from(body)));
+    }
+
+    //Answers the next `times` requests to "METHOD /path" with a 502 Bad Gateway
+    pub fn fail_next(&self, method: &str, path: &str, times: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.failures.insert(format!("{} {}", method, path), times);
+    }
+
+    //Seeds an existing comment on an issue, returns its id
+    pub fn add_comment(&self, issue_number: u64, user: &str, body: &str) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        let id         = state.next_id;
+        state.comments.push(FakeComment {
+            id:    id,
+            issue: issue_number.to_string(),
+            user:  String::from(user),
+            body:  String::from(body)
+        });
+        id
+    }
+
+    //Current bodies of the comments on an issue, in creation order
+    pub fn comments(&self, issue_number: u64) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state.comments.iter()
+            .filter(|comment| comment.issue == issue_number.to_string())
+            .map(|comment| comment.body.clone())
+            .collect()
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    pub fn requests_to(&self, method: &str, path: &str) -> Vec<RecordedRequest> {
+        self.requests().into_iter()
+            .filter(|request| request.method == method && request.path == path)
+            .collect()
+    }
+
+    //Waits until at least `count` requests were made to "METHOD /path", the
+    //bot sends some of its requests from other threads
+    pub fn wait_for_requests(&self, method: &str, path: &str, count: usize) -> Vec<RecordedRequest> {
+        for _ in 0..100 {
+            let requests = self.requests_to(method, path);
+            if requests.len() >= count {
+                return requests;
+            }
+            thread::sleep(time::Duration::from_millis(50));
+        }
+        panic!("Timed out waiting for {} request(s) to {} {}, got: {:?}", count, method, path, self.requests())
+    }
+
+    //Waits until a comment on the issue contains `text`, returns all the comments
+    pub fn wait_for_comment(&self, issue_number: u64, text: &str) -> Vec<String> {
+        for _ in 0..100 {
+            let comments = self.comments(issue_number);
+            if comments.iter().any(|comment| comment.contains(text)) {
+                return comments;
+            }
+            thread::sleep(time::Duration::from_millis(50));
+        }
+        panic!("Timed out waiting for a comment containing {:?}, got: {:?}", text, self.comments(issue_number))
+    }
+}
+
+impl Drop for FakeGithub {
+    fn drop(&mut self) {
+        let _ = self.listening.close();
+    }
+}
+
+impl middleware::Handler for FakeGithubHandler {
+    fn handle(&self, request: &mut Request) -> IronResult<Response> {
+
+        let mut body = String::new();
+        let _        = request.body.read_to_string(&mut body);
+
+        let recorded = RecordedRequest {
+            method: format!("{}", request.method),
+            path:   format!("/{}", request.url.path.join("/")),
+            query:  request.url.query.clone().unwrap_or(String::new()),
+            body:   body
+        };
+
+        let mut state = self.state.lock().unwrap();
+        state.requests.push(recorded.clone());
+
+        let route = format!("{} {}", recorded.method, recorded.path);
+        if let Some(remaining) = state.failures.get_mut(&route) {
+            if *remaining > 0 {
+                *remaining -= 1;
+                return Ok(Response::with((StatusCode::BadGateway, "{\"message\": \"Bad Gateway\"}")))
+            }
+        }
+
+        let canned                = state.responses.get(&route).cloned();
+        let (code, response_body) = match canned {
+            Some(response) => response,
+            None           => default_response(&mut *state, &recorded)
+        };
+
+        Ok(Response::with((StatusCode::from_u16(code), response_body)))
+    }
+}
+
+//Minimal stateful imitation of the endpoints used by the bot
+fn default_response(state: &mut FakeGithubState, request: &RecordedRequest) -> (u16, String) {
+
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    let method              = &request.method[..];
+    let not_found           = (404, String::from("{\"message\": \"Not Found\"}"));
+
+    if segments.len() < 4 || segments[0] != "repos" {
+        return not_found
+    }
+    let endpoint = &segments[3..];
+
+    if method == "POST" && route_matches(endpoint, "hooks").is_some() {
+        state.next_id += 1;
+        return (201, format!("{{\"id\": {}}}", state.next_id))
+    }
+
+    if let Some(args) = route_matches(endpoint, "issues/*/comments") {
+        if method == "GET" {
+            let comments: Vec<String> = state.comments.iter()
+                .filter(|comment| comment.issue == args[0])
+                .map(|comment| comment_json(comment))
+                .collect();
+            return (200, format!("[{}]", comments.join(",")))
+        }
+        if method == "POST" {
+            state.next_id += 1;
+            let comment = FakeComment {
+                id:    state.next_id,
+                issue: args[0].clone(),
+                user:  String::from(TEST_BOT_NAME),
+                body:  json_string_field(&request.body, "body")
+            };
+            let response = comment_json(&comment);
+            state.comments.push(comment);
+            return (201, response)
+        }
+    }
+
+    if let Some(args) = route_matches(endpoint, "issues/comments/*") {
+        if method == "PATCH" {
+            let body = json_string_field(&request.body, "body");
+            return match state.comments.iter_mut().find(|comment| comment.id.to_string() == args[0]) {
+                Some(comment) => {
+                    comment.body = body;
+                    (200, comment_json(comment))
+                }
+                None          => not_found
+            }
+        }
+        if method == "DELETE" {
+            state.comments.retain(|comment| comment.id.to_string() != args[0]);
+            return (204, String::new())
+        }
+    }
+
+    if route_matches(endpoint, "issues/comments/*/reactions").is_some() {
+        if method == "GET" {
+            return (200, String::from("[]"))
+        }
+        if method == "POST" {
+            state.next_id += 1;
+            return (201, format!("{{\"id\": {}, \"content\": \"{}\"}}", state.next_id, json_string_field(&request.body, "content")))
+        }
+    }
+
+    if method == "DELETE" && route_matches(endpoint, "issues/comments/*/reactions/*").is_some() {
+        return (204, String::new())
+    }
+
+    if method == "GET" && route_matches(endpoint, "labels").is_some() {
+        return (200, String::from("[]"))
+    }
+
+    if route_matches(endpoint, "issues/*/labels").is_some() || route_matches(endpoint, "issues/*/labels/*").is_some() {
+        return (200, String::from("[]"))
+    }
+
+    if method == "POST" && route_matches(endpoint, "statuses/*").is_some() {
+        return (201, String::from("{}"))
+    }
+
+    if method == "GET" && route_matches(endpoint, "commits/*/status").is_some() {
+        return (200, String::from("{\"state\": \"success\", \"statuses\": []}"))
+    }
+
+    if method == "GET" && route_matches(endpoint, "commits/*/check-runs").is_some() {
+        return (200, String::from("{\"total_count\": 0, \"check_runs\": []}"))
+    }
+
+    if method == "PUT" && route_matches(endpoint, "pulls/*/merge").is_some() {
+        return (200, String::from("{\"sha\": \"1111111111111111111111111111111111111111\", \"merged\": true}"))
+    }
+
+    if method == "GET" {
+        if let Some(args) = route_matches(endpoint, "pulls/*") {
+            return (200, format!("{{\"number\": {}, \"state\": \"open\", \"draft\": false, \"mergeable\": true, \"head\": {{\"sha\": \"0000000000000000000000000000000000000000\"}}}}", args[0]))
+        }
+        if route_matches(endpoint, "pulls/*/reviews").is_some() {
+            return (200, String::from("[]"))
+        }
+    }
+
+    not_found
+}
+
+//Matches path segments against a pattern like "issues/*/comments",
+//returns the segments matched by the wildcards
+fn route_matches(segments: &[&str], pattern: &str) -> Option<Vec<String>> {
+    let pattern_segments: Vec<&str> = pattern.split('/').collect();
+    if pattern_segments.len() != segments.len() {
+        return None
+    }
+    let mut args: Vec<String> = Vec::new();
+    for (segment, pattern_segment) in segments.iter().zip(pattern_segments.iter()) {
+        if *pattern_segment == "*" {
+            args.push(String::from(*segment));
+        } else if segment != pattern_segment {
+            return None
+        }
+    }
+    Some(args)
+}
+
+fn comment_json(comment: &FakeComment) -> String {
+    let mut user: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+    user.insert(String::from("login"), serde_json::Value::String(comment.user.clone()));
+
+    let mut object: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+    object.insert(String::from("id"),   serde_json::Value::U64(comment.id));
+    object.insert(String::from("body"), serde_json::Value::String(comment.body.clone()));
+    object.insert(String::from("user"), serde_json::Value::Object(user));
+    serde_json::to_string(&serde_json::Value::Object(object)).unwrap()
+}
+
+fn json_string_field(body: &String, field: &'static str) -> String {
+    let value: serde_json::Value = match serde_json::from_str(&body[..]) {
+        Ok(value) => value,
+        Err(err)  => panic!("The bot sent invalid JSON ({}): {}", err, body)
+    };
+    match value.as_object() {
+        Some(object) => webhooks::extract_json_string(object, field).unwrap_or(String::new()),
+        None         => String::new()
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                       Bot setup                        //
+////////////////////////////////////////////////////////////
+
+//Creates a fresh file in the temp directory, i.e for the config or the bot's state
+pub fn temp_path(name: &str) -> String {
+    let mut path = env::temp_dir();
+    path.push(format!("hunter-bot-test-{}-{}", TEMP_FILE_COUNTER.fetch_add(1, Ordering::SeqCst), name));
+    String::from(path.to_str().unwrap())
+}
+
+//Creates a fresh directory in the temp directory, returned with a trailing separator
+pub fn temp_dir(name: &str) -> String {
+    let path = temp_path(name);
+    fs::create_dir_all(&path).unwrap();
+    format!("{}/", path)
+}
+
+//Config for a bot following TEST_FOLLOW_REPO through the fake API at api_url
+pub fn test_config(api_url: &str) -> config::ConfigHandler {
+
+    let config_path = temp_path("HunterBotConfig.toml");
+    let data_dir    = temp_dir("data");
+    let config_data = format!(r#"
+[config]
+data_dir           = "{}"
+github_api_url     = "{}"
+github_bot_name    = "{}"
+github_bot_token   = "bot-token"
+github_follow_repo = "{}"
+github_owner_name  = "{}"
+github_owner_token = "owner-token"
+listen_port        = "0"
+local_ip_address   = "127.0.0.1"
+public_ip_address  = "127.0.0.1"
+whitelist          = ["whitelisted"]
+
+[state]
+github_webhook_secret = "{}"
+"#, data_dir, api_url, TEST_BOT_NAME, TEST_FOLLOW_REPO, TEST_OWNER_NAME, TEST_WEBHOOK_SECRET);
+
+    let mut file = File::create(&config_path).unwrap();
+    file.write_all(config_data.as_bytes()).unwrap();
+
+    let mut config = config::ConfigHandler::new();
+    config.load(&config_path).unwrap();
+    config
+}
+
+//Serves the bot's WebhookHandler on a random local port, events are sent to queue_tx
+pub fn start_webhook_server(tsconfig: &Arc<Mutex<config::ConfigHandler>>, queue_tx: Sender<webhooks::WebhookEvent>) -> Listening {
+    let handler = webhooks::WebhookHandler::new(tsconfig.clone(), Arc::new(Mutex::new(queue_tx)));
+    match Iron::new(handler).http("127.0.0.1:0") {
+        Ok(listening) => listening,
+        Err(err)      => panic!("Failed to start the webhook server: {}", err)
+    }
+}
+
+//Waits until the outbound queue delivered (or gave up on) every message
+pub fn wait_for_delivery(outbound: &outbound::OutboundQueue) {
+    for _ in 0..100 {
+        if outbound.pending() == 0 {
+            return;
+        }
+        thread::sleep(time::Duration::from_millis(50));
+    }
+    panic!("Timed out waiting for the outbound queue: {:?}", outbound)
+}
+
+//Posts a webhook signed with TEST_WEBHOOK_SECRET, returns the status and body of the response
+pub fn send_webhook(server: &SocketAddr, event: &str, payload: &str) -> (StatusCode, String) {
+    let signature = webhooks::compute_webhook_signature(TEST_WEBHOOK_SECRET, payload).unwrap();
+    send_webhook_with_signature(server, event, payload, &signature)
+}
+
+pub fn send_webhook_with_signature(server: &SocketAddr, event: &str, payload: &str, signature: &str) -> (StatusCode, String) {
+
+    let mut headers = Headers::new();
+    headers.set_raw("X-GitHub-Event",    vec![event.as_bytes().to_vec()]);
+    headers.set_raw("X-Hub-Signature",   vec![signature.as_bytes().to_vec()]);
+    headers.set_raw("X-GitHub-Delivery", vec![TEST_DELIVERY_ID.as_bytes().to_vec()]);
+
+    let mut response = match Client::new().post(&format!("http://{}/webhook", server)[..]).headers(headers).body(payload).send() {
+        Ok(response) => response,
+        Err(err)     => panic!("Failed to send the webhook: {}", err)
+    };
+
+    let mut body = String::new();
+    let _        = response.read_to_string(&mut body);
+    (response.status, body)
+}
+
+//Payload of an "issue_comment" webhook for a newly created comment
+pub fn issue_comment_payload(user: &str, issue_number: u64, comment_id: u64, body: &str) -> String {
+
+    let mut user_object: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+    user_object.insert(String::from("login"), serde_json::Value::String(String::from(user)));
+
+    let mut comment: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+    comment.insert(String::from("id"),   serde_json::Value::U64(comment_id));
+    comment.insert(String::from("body"), serde_json::Value::String(String::from(body)));
+    comment.insert(String::from("user"), serde_json::Value::Object(user_object.clone()));
+
+    let mut issue: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+    issue.insert(String::from("id"),     serde_json::Value::U64(issue_number + 100000));
+    issue.insert(String::from("number"), serde_json::Value::U64(issue_number));
+    issue.insert(String::from("user"),   serde_json::Value::Object(user_object));
+
+    let mut payload: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+    payload.insert(String::from("action"),  serde_json::Value::String(String::from("created")));
+    payload.insert(String::from("comment"), serde_json::Value::Object(comment));
+    payload.insert(String::from("issue"),   serde_json::Value::Object(issue));
+
+    serde_json::to_string(&serde_json::Value::Object(payload)).unwrap()
+}
+
+//Built-in commands a user with the role can run, as the replies list them: "`cancel`, `help`, ..."
+pub fn available_commands(role: Role) -> String {
+    commands::CommandRegistry::builtin().commands().iter()
+        .filter(|&(_, command)| command.allows(role, false))
+        .map(|(name, _)| response::inline_code(name))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
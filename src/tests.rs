@@ -0,0 +1,1164 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+//End to end tests: signed webhook -> WebhookHandler -> CommandHandler -> fake Github API
+
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time;
+
+extern crate hyper;
+use hyper::status::StatusCode;
+
+extern crate serde_json;
+
+extern crate toml;
+
+use args;
+use audit;
+use automerge;
+use commands;
+use config;
+use help;
+use mentions;
+use outbound;
+use permissions;
+use permissions::Role;
+use queue;
+use response;
+use sticky;
+use store;
+use templates;
+use test_support;
+use test_support::FakeGithub;
+use webhooks;
+use whitelist;
+
+fn comments_path(issue_number: u64) -> String {
+    format!("/repos/{}/issues/{}/comments", test_support::TEST_FOLLOW_REPO, issue_number)
+}
+
+#[test]
+fn ping_is_answered_with_a_comment() {
+
+    let github       = FakeGithub::start();
+    let tsconfig     = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    let (tx, rx)     = channel();
+    let mut server   = test_support::start_webhook_server(&tsconfig, tx);
+    let payload      = test_support::issue_comment_payload("octocat", 7, 42, "@hunter-bot ping");
+
+    let (status, body) = test_support::send_webhook(&server.socket, "issue_comment", &payload);
+    assert_eq!(status, StatusCode::Ok);
+    assert_eq!(body, "Received.");
+
+    let event = rx.try_recv().unwrap();
+    assert_eq!(event.user,    "octocat");
+    assert_eq!(event.number,  7);
+    assert_eq!(event.commands, vec!["ping"]);
+
+    let outbound = outbound::OutboundQueue::start(&tsconfig);
+    commands::CommandHandler::new(&tsconfig, &outbound).parse_command(event);
+
+    let requests = github.wait_for_requests("POST", &comments_path(7), 1);
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].query, "access_token=bot-token");
+    assert_eq!(github.comments(7), vec![String::from("@octocat Pong")]);
+
+    let _ = server.close();
+}
+
+#[test]
+fn webhook_with_invalid_signature_is_rejected() {
+
+    let github     = FakeGithub::start();
+    let tsconfig   = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    let (tx, rx)   = channel();
+    let mut server = test_support::start_webhook_server(&tsconfig, tx);
+    let payload    = test_support::issue_comment_payload("octocat", 7, 42, "@hunter-bot ping");
+
+    let (status, _) = test_support::send_webhook_with_signature(&server.socket, "issue_comment", &payload, "sha1=0000");
+    assert_eq!(status, StatusCode::BadRequest);
+    assert!(rx.try_recv().is_err());
+    assert!(github.requests().is_empty());
+
+    let _ = server.close();
+}
+
+#[test]
+fn comment_without_mention_is_skipped() {
+
+    let github     = FakeGithub::start();
+    let tsconfig   = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    let (tx, rx)   = channel();
+    let mut server = test_support::start_webhook_server(&tsconfig, tx);
+    let payload    = test_support::issue_comment_payload("octocat", 7, 42, "Looks good to me");
+
+    let (status, body) = test_support::send_webhook(&server.socket, "issue_comment", &payload);
+    assert_eq!(status, StatusCode::Ok);
+    assert_eq!(body, "Skipped.");
+    assert!(rx.try_recv().is_err());
+
+    let _ = server.close();
+}
+
+#[test]
+fn reply_is_retried_after_a_server_error() {
+
+    let github     = FakeGithub::start();
+    let tsconfig   = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    let (tx, rx)   = channel();
+    let mut server = test_support::start_webhook_server(&tsconfig, tx);
+    let payload    = test_support::issue_comment_payload("octocat", 7, 42, "@hunter-bot ping");
+
+    tsconfig.lock().unwrap().set_string("config", "outbound_retry_delay", "1");
+    github.fail_next("POST", &comments_path(7), 1);
+
+    test_support::send_webhook(&server.socket, "issue_comment", &payload);
+    let outbound = outbound::OutboundQueue::start(&tsconfig);
+    commands::CommandHandler::new(&tsconfig, &outbound).parse_command(rx.try_recv().unwrap());
+
+    github.wait_for_requests("POST", &comments_path(7), 2);
+    assert_eq!(github.comments(7), vec![String::from("@octocat Pong")]);
+
+    let _ = server.close();
+}
+
+#[test]
+fn undeliverable_reply_is_dead_lettered() {
+
+    let github     = FakeGithub::start();
+    let tsconfig   = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    let (tx, rx)   = channel();
+    let mut server = test_support::start_webhook_server(&tsconfig, tx);
+    let payload    = test_support::issue_comment_payload("octocat", 7, 42, "@hunter-bot ping");
+
+    tsconfig.lock().unwrap().set_string("config", "outbound_max_attempts", "1");
+    github.fail_next("POST", &comments_path(7), 1);
+
+    test_support::send_webhook(&server.socket, "issue_comment", &payload);
+    let outbound = outbound::OutboundQueue::start(&tsconfig);
+    commands::CommandHandler::new(&tsconfig, &outbound).parse_command(rx.try_recv().unwrap());
+
+    github.wait_for_requests("POST", &comments_path(7), 1);
+    test_support::wait_for_delivery(&outbound);
+
+    let dead_letters = outbound::dead_letters(&tsconfig).unwrap();
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0].1.comment_body(), "@octocat Pong");
+    assert!(github.comments(7).is_empty());
+
+    let _ = server.close();
+}
+
+#[test]
+fn message_markdown_survives_json_serialization() {
+
+    let message = response::Message::text("Results for \"Boost\":")
+        .table(vec!["Toolchain", "Result"], vec![vec![String::from("gcc|clang"), String::from("ok\nwarnings")]])
+        .details("Log <full>", response::Message::new().code_block("", "a ``` fence\n\\ backslash"))
+        .mention("octocat");
+
+    let markdown = message.to_markdown();
+    assert!(markdown.starts_with("@octocat Results for \"Boost\":\n\n| Toolchain | Result |\n| --- | --- |\n| gcc\\|clang | ok<br>warnings |"));
+    assert!(markdown.contains("<summary>Log &lt;full&gt;</summary>"));
+    assert!(markdown.contains("````\na ``` fence\n\\ backslash\n````"));
+
+    let json: serde_json::Value = serde_json::from_str(&response::comment_json(&markdown).unwrap()).unwrap();
+    assert_eq!(json.find("body").and_then(|body| body.as_str()), Some(&markdown[..]));
+}
+
+#[test]
+fn oversized_markdown_is_split_outside_of_code_fences() {
+
+    let code     = (0..40).map(|line| format!("line {}", line)).collect::<Vec<String>>().join("\n");
+    let markdown = response::Message::text("Build log:").code_block("text", &code).paragraph("Done.").to_markdown();
+    let parts    = response::split_markdown(&markdown, 120);
+
+    assert!(parts.len() > 2);
+    for part in &parts {
+        assert!(part.chars().count() <= 120, "part too long: {}", part);
+        assert_eq!(part.matches("```").count() % 2, 0, "unbalanced fence: {}", part);
+    }
+    assert_eq!(parts[0].lines().next(), Some("Build log:"));
+    assert_eq!(parts.last().unwrap().lines().last(), Some("Done."));
+    assert_eq!(response::truncate_markdown(&markdown, 120), parts[0]);
+}
+
+#[test]
+fn sticky_reply_edits_the_previous_comment() {
+
+    let github   = FakeGithub::start();
+    let tsconfig = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    let outbound = outbound::OutboundQueue::start(&tsconfig);
+
+    //Same marker but not made by the bot
+    github.add_comment(7, "octocat", &sticky::with_marker("Quoting the bot", "status"));
+
+    let mut event = webhooks::WebhookEvent::new();
+    event.user    = String::from("octocat");
+    event.number  = 7;
+
+    commands::respond(&tsconfig, &outbound, event.clone(), response::Message::text("Tests: pending").sticky("status"));
+    test_support::wait_for_delivery(&outbound);
+    commands::respond(&tsconfig, &outbound, event.clone(), response::Message::text("Tests: passed").sticky("status"));
+    test_support::wait_for_delivery(&outbound);
+
+    let comments = github.comments(7);
+    assert_eq!(comments.len(), 2);
+    assert_eq!(comments[1], sticky::with_marker("@octocat Tests: passed", "status"));
+    assert_eq!(github.requests_to("POST", &comments_path(7)).len(), 1);
+}
+
+#[test]
+fn command_line_is_tokenized_like_a_shell() {
+
+    let parsed = args::CommandArgs::from_tokens(args::tokenize(r#"test Boost --toolchain "gcc 7" --config='a b' --verbose -- --not-an-option it\'s"#).unwrap()).unwrap();
+    assert_eq!(parsed.name, "test");
+    assert_eq!(parsed.positional, vec!["Boost", "--not-an-option", "it's"]);
+    assert_eq!(parsed.option("toolchain"), Some("gcc 7"));
+    assert_eq!(parsed.option("config"),    Some("a b"));
+    assert_eq!(parsed.option("verbose"),   Some(""));
+
+    assert_eq!(args::tokenize(r#"say "a \"quoted\" \\ word""#).unwrap(), vec!["say", r#"a "quoted" \ word"#]);
+    assert!(args::tokenize("test 'Boost").is_err());
+    assert!(args::CommandArgs::from_tokens(args::tokenize("   ").unwrap()).is_err());
+}
+
+#[test]
+fn every_mention_runs_its_own_command() {
+
+    let github     = FakeGithub::start();
+    let tsconfig   = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    let (tx, rx)   = channel();
+    let mut server = test_support::start_webhook_server(&tsconfig, tx);
+    let payload    = test_support::issue_comment_payload("octocat", 7, 42, "Thanks!\r\n@hunter-bot ping\r\n@hunter-bot frobnicate\r\n@hunter-bot ping");
+
+    test_support::send_webhook(&server.socket, "issue_comment", &payload);
+    let event = rx.try_recv().unwrap();
+    assert_eq!(event.commands, vec!["ping", "frobnicate", "ping"]);
+
+    let outbound = outbound::OutboundQueue::start(&tsconfig);
+    commands::CommandHandler::new(&tsconfig, &outbound).parse_command(event);
+    test_support::wait_for_delivery(&outbound);
+
+    let comments = github.comments(7);
+    assert_eq!(comments.len(), 1);
+    assert!(comments[0].starts_with("@octocat :white_check_mark: `ping`\n\nPong\n\n:no_entry_sign: `frobnicate`\n\nSorry the command `frobnicate` was not found."));
+    assert!(comments[0].ends_with(":white_check_mark: `ping`\n\nPong"));
+
+    let _ = server.close();
+}
+
+#[test]
+fn unknown_command_suggests_the_closest_names() {
+
+    let github     = FakeGithub::start();
+    let tsconfig   = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    let (tx, rx)   = channel();
+    let mut server = test_support::start_webhook_server(&tsconfig, tx);
+    let payload    = test_support::issue_comment_payload("octocat", 7, 42, "@hunter-bot pnig\r\n@hunter-bot COMMANDS");
+
+    test_support::send_webhook(&server.socket, "issue_comment", &payload);
+    let event = rx.try_recv().unwrap();
+
+    let outbound = outbound::OutboundQueue::start(&tsconfig);
+    commands::CommandHandler::new(&tsconfig, &outbound).parse_command(event);
+    test_support::wait_for_delivery(&outbound);
+
+    let comments = github.comments(7);
+    assert_eq!(comments.len(), 1);
+    assert!(comments[0].contains("Sorry the command `pnig` was not found.\n\nDid you mean `ping`?"));
+    assert!(comments[0].contains(&format!("Commands you can use: {}.", test_support::available_commands(Role::IssueAuthor))[..]));
+    assert!(test_support::available_commands(Role::IssueAuthor).contains("`ping`") && !test_support::available_commands(Role::IssueAuthor).contains("`merge`"));
+    assert!(comments[0].contains(":white_check_mark: `COMMANDS`"));
+
+    assert_eq!(commands::edit_distance("pnig", "ping"), 2);
+    assert_eq!(commands::edit_distance("", "help"), 4);
+    assert_eq!(commands::edit_distance("deadleters", "deadletters"), 1);
+
+    let _ = server.close();
+}
+
+#[test]
+fn mentions_in_quotes_code_and_other_names_are_ignored() {
+
+    let body = vec![
+        "> @hunter-bot merge",
+        "```",
+        "@hunter-bot merge",
+        "```",
+        "    @hunter-bot merge",
+        "<!--",
+        "@hunter-bot merge",
+        "-->",
+        "@hunter-bot2 merge",
+        "@hunter-bot-dev merge",
+        "Please @hunter-bot merge",
+        "@Hunter-Bot: test \"Boost 1.62\""
+    ].join("\n");
+
+    assert_eq!(mentions::extract_commands(&body, "hunter-bot", None), vec!["test \"Boost 1.62\""]);
+    assert_eq!(mentions::extract_commands("@hunter.bot ping", "hunter.bot", None), vec!["ping"]);
+    assert!(mentions::extract_commands("@hunterXbot ping", "hunter.bot", None).is_empty());
+    assert_eq!(mentions::extract_commands("@hunter-bot", "hunter-bot", None), vec![""]);
+}
+
+#[test]
+fn commands_can_start_with_the_prefix_of_the_repository() {
+
+    let body = "/hunter test Boost\n/hunterbot merge\n> /hunter merge\n!hb merge\n@hunter-bot ping";
+    assert_eq!(mentions::extract_commands(body, "hunter-bot", Some("/hunter")), vec!["test Boost", "ping"]);
+    assert_eq!(mentions::extract_commands("!ping\n!", "hunter-bot", Some("!")), vec!["ping"]);
+
+    let github     = FakeGithub::start();
+    let tsconfig   = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    let (tx, rx)   = channel();
+    let mut server = test_support::start_webhook_server(&tsconfig, tx);
+    let payload    = test_support::issue_comment_payload("octocat", 7, 42, "/hunter ping\r\n!hb help");
+
+    tsconfig.lock().unwrap().set_string("config", "command_prefix", "!hb");
+    tsconfig.lock().unwrap().set_string("prefixes", "ruslo/other", "/hunter");
+    test_support::send_webhook(&server.socket, "issue_comment", &payload);
+    assert_eq!(rx.try_recv().unwrap().commands, vec!["help"]);
+
+    //The prefix of the repository wins over the one of every repository
+    tsconfig.lock().unwrap().set_string("prefixes", test_support::TEST_FOLLOW_REPO, "/hunter");
+    test_support::send_webhook(&server.socket, "issue_comment", &payload);
+    assert_eq!(rx.try_recv().unwrap().commands, vec!["ping"]);
+
+    let _ = server.close();
+}
+
+#[test]
+fn help_is_generated_from_the_registry() {
+
+    let registry = commands::CommandRegistry::builtin();
+
+    let everyone = help::command_table(&registry, Role::Anyone, false, &templates::Templates::new()).to_markdown();
+    assert!(everyone.contains("| `ping` |  | `ping` | Checks that the bot is alive. |"));
+    assert!(!everyone.contains("`deadletters`"));
+    assert!(help::command_table(&registry, Role::Anyone, true, &templates::Templates::new()).to_markdown().contains("| `deadletters` | `dead-letters`, `undelivered` | `deadletters [count]` |"));
+    assert!(!help::command_table(&registry, Role::Admin, false, &templates::Templates::new()).to_markdown().contains("`deadletters`"));
+
+    let (name, command) = registry.find("undelivered").unwrap();
+    let usage           = help::command_usage(&name, command).to_markdown();
+    assert!(usage.starts_with("`deadletters`: Lists the replies the bot gave up on delivering.\n\n```\ndeadletters [count]\n```"));
+    assert!(usage.contains("* Can be used by: The owner and whitelisted users"));
+
+    //docs/commands.rst is regenerated with "hunter-bot docs --format rst --output docs/commands.rst"
+    assert_eq!(help::restructured_text(&registry), include_str!("../docs/commands.rst"));
+}
+
+#[test]
+fn user_role_combines_config_event_and_collaborator_permission() {
+
+    let github      = FakeGithub::start();
+    let tsconfig    = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    let permissions = permissions::PermissionCache::new(&tsconfig);
+    let permission  = format!("/repos/{}/collaborators/octocat/permission", test_support::TEST_FOLLOW_REPO);
+    github.respond_with("GET", &permission, 200, "{\"permission\": \"write\", \"role_name\": \"maintain\"}");
+
+    let mut event      = webhooks::WebhookEvent::new();
+    event.user         = String::from("octocat");
+    event.issue_author = String::from("someone-else");
+
+    assert_eq!(permissions.user_role(&event, Role::Owner), Role::Maintain);
+    assert!(permissions.has_role(&event, Role::Write));
+    assert!(!permissions.has_role(&event, Role::Admin));
+    assert!(!permissions.is_whitelisted(&event));
+    assert_eq!(github.requests_to("GET", &permission).len(), 1);
+
+    //Authors of the issue do not need to be collaborators for IssueAuthor commands
+    event.user         = String::from("newcomer");
+    event.issue_author = String::from("newcomer");
+    assert!(permissions.has_role(&event, Role::IssueAuthor));
+    assert!(!permissions.has_role(&event, Role::Triage));
+
+    //The whitelist is a grant of its own, it does not give any repository role
+    event.user = String::from("whitelisted");
+    assert!(permissions.is_whitelisted(&event));
+    assert_eq!(permissions.user_role(&event, Role::Owner), Role::Anyone);
+    assert!(!permissions.has_role(&event, Role::Write));
+    event.user = String::from(test_support::TEST_OWNER_NAME);
+    assert_eq!(permissions.user_role(&event, Role::Owner), Role::Owner);
+    assert!(permissions.is_whitelisted(&event));
+
+    //The owner is never looked up
+    assert_eq!(github.requests().len(), 3);
+}
+
+#[test]
+fn owner_manages_the_whitelist() {
+
+    let github   = FakeGithub::start();
+    let tsconfig = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    github.respond_with("GET", "/users/octocat", 200, "{\"login\": \"OctoCat\"}");
+
+    let mut event    = webhooks::WebhookEvent::new();
+    event.user       = String::from(test_support::TEST_OWNER_NAME);
+    event.number     = 7;
+    event.commands   = vec![String::from("whitelist add octocat"), String::from("whitelist add ghost"), String::from("whitelist list")];
+
+    let outbound = outbound::OutboundQueue::start(&tsconfig);
+    let handler  = commands::CommandHandler::new(&tsconfig, &outbound);
+    handler.parse_command(event.clone());
+    test_support::wait_for_delivery(&outbound);
+
+    let comments = github.comments(7);
+    assert_eq!(comments.len(), 1);
+    assert!(comments[0].contains(":white_check_mark: `whitelist add octocat`\n\n`OctoCat` was added to the whitelist."));
+    assert!(comments[0].contains(":x: `whitelist add ghost`\n\nAn error occurred while executing the command: There is no Github user named `ghost`."));
+    assert!(comments[0].contains(&format!("| `OctoCat` | {} |", test_support::TEST_OWNER_NAME)));
+
+    //The config file was saved
+    let mut reloaded = config::ConfigHandler::new();
+    let config_path  = tsconfig.lock().unwrap().file_path.to_str().unwrap().to_string();
+    reloaded.load(&config_path).unwrap();
+    assert!(reloaded.whitelist_validate_user(String::from("OctoCat")));
+
+    //Only the owner can change it
+    event.user     = String::from("whitelisted");
+    event.commands = vec![String::from("whitelist remove OctoCat")];
+    handler.parse_command(event.clone());
+    test_support::wait_for_delivery(&outbound);
+    assert!(github.comments(7)[1].contains("Sorry! You need to be the owner to run that command."));
+
+    event.user = String::from(test_support::TEST_OWNER_NAME);
+    handler.parse_command(event);
+    test_support::wait_for_delivery(&outbound);
+    assert!(!tsconfig.lock().unwrap().whitelist_validate_user(String::from("OctoCat")));
+
+    let changes: Vec<(String, String)> = whitelist::changes(&tsconfig).unwrap().into_iter().map(|change| (change.action, change.user)).collect();
+    assert_eq!(changes, vec![(String::from("add"), String::from("OctoCat")), (String::from("remove"), String::from("OctoCat"))]);
+}
+
+#[test]
+fn please_runs_the_command_waiting_for_it() {
+
+    let github   = FakeGithub::start();
+    let tsconfig = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    tsconfig.lock().unwrap().set_string("please", "ping", "true");
+
+    let outbound = outbound::OutboundQueue::start(&tsconfig);
+    let handler  = commands::CommandHandler::new(&tsconfig, &outbound);
+
+    let mut event  = webhooks::WebhookEvent::new();
+    event.user     = String::from("octocat");
+    event.number   = 7;
+    event.commands = vec![String::from("ping")];
+    handler.parse_command(event.clone());
+
+    //Someone else's please does not count
+    let mut other  = event.clone();
+    other.user     = String::from("someone-else");
+    other.commands = vec![String::from("please")];
+    handler.parse_command(other);
+
+    event.commands = vec![String::from("please")];
+    handler.parse_command(event.clone());
+    handler.parse_command(event);
+    test_support::wait_for_delivery(&outbound);
+
+    let comments = github.comments(7);
+    assert_eq!(comments.len(), 4);
+    assert!(comments[0].starts_with("@octocat Whats the magic word?\n\nSay `please` within 10 minute(s)"));
+    assert!(comments[1].starts_with("@someone-else You're welcome, but there is no command of yours waiting"));
+    assert_eq!(comments[2], "@octocat Thanks! Running `ping`:\n\nPong");
+    assert!(comments[3].starts_with("@octocat You're welcome, but there is no command of yours waiting"));
+}
+
+#[test]
+fn dispatched_commands_are_audited() {
+
+    let github     = FakeGithub::start();
+    let tsconfig   = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    let (tx, rx)   = channel();
+    let mut server = test_support::start_webhook_server(&tsconfig, tx);
+    let payload    = test_support::issue_comment_payload("octocat", 7, 42, "@hunter-bot ping\r\n@hunter-bot deadletters");
+
+    test_support::send_webhook(&server.socket, "issue_comment", &payload);
+    let outbound = outbound::OutboundQueue::start(&tsconfig);
+    let handler  = commands::CommandHandler::new(&tsconfig, &outbound);
+    handler.parse_command(rx.try_recv().unwrap());
+
+    let entries = audit::entries(&tsconfig, None, None).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].delivery, test_support::TEST_DELIVERY_ID);
+    assert_eq!((&entries[0].user[..], &entries[0].role[..], entries[0].number, &entries[0].command[..], &entries[0].status[..]), ("octocat", "Anyone", 7, "ping", "Succeeded"));
+    assert_eq!((&entries[1].command[..], &entries[1].status[..]), ("deadletters", "Rejected"));
+    //The role comes from the permission check, only "deadletters" needed to look it up
+    assert_eq!(github.requests_to("GET", &format!("/repos/{}/collaborators/octocat/permission", test_support::TEST_FOLLOW_REPO)).len(), 1);
+
+    assert!(audit::entries(&tsconfig, Some("someone-else"), None).unwrap().is_empty());
+    assert!(audit::entries(&tsconfig, Some("OctoCat"), Some(entries[0].at + 3600)).unwrap().is_empty());
+    assert_eq!(audit::parse_since("2016-11-30", 0), Ok(1480464000));
+    assert_eq!(audit::parse_since("7d", 1000000), Ok(1000000 - 7 * 24 * 3600));
+    assert!(audit::parse_since("a week", 0).is_err());
+
+    let csv = audit::to_csv(&entries);
+    assert!(csv.starts_with("at,delivery,user,role,number,command,status,duration_ms\n"));
+    assert!(csv.contains(&format!(",{},octocat,Anyone,7,deadletters,Rejected,", test_support::TEST_DELIVERY_ID)[..]));
+
+    //Only the owner can read it
+    let mut event  = webhooks::WebhookEvent::new();
+    event.user     = String::from(test_support::TEST_OWNER_NAME);
+    event.number   = 8;
+    event.commands = vec![String::from("audit octocat")];
+    handler.parse_command(event);
+    test_support::wait_for_delivery(&outbound);
+    assert!(github.comments(8)[0].contains("2 command(s), showing the last 2:"));
+
+    let _ = server.close();
+}
+
+#[test]
+fn status_reports_the_bot_without_its_secrets() {
+
+    let github   = FakeGithub::start();
+    let tsconfig = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    tsconfig.lock().unwrap().set_string("state", "github_webhook_id", "1234");
+    {
+        let mut repo = toml::Table::new();
+        repo.insert(String::from("GITHUB_API_KEY"), toml::Value::String(String::from("nested-key")));
+        let mut repos = toml::Table::new();
+        repos.insert(String::from("hunter"), toml::Value::Table(repo));
+        tsconfig.lock().unwrap().toml_data.insert(String::from("repos"), toml::Value::Table(repos));
+    }
+    github.respond_with("GET", "/rate_limit", 200, "{\"resources\": {\"core\": {\"limit\": 5000, \"remaining\": 4990, \"reset\": 1480464000}}}");
+
+    let outbound = outbound::OutboundQueue::start(&tsconfig);
+    let handler  = commands::CommandHandler::new(&tsconfig, &outbound);
+
+    let mut event  = webhooks::WebhookEvent::new();
+    event.user     = String::from("whitelisted");
+    event.number   = 7;
+    event.commands = vec![String::from("status")];
+    handler.parse_command(event);
+    test_support::wait_for_delivery(&outbound);
+
+    let status = &github.comments(7)[0];
+    assert!(status.contains(&format!("| Version | {} |", env!("CARGO_PKG_VERSION"))[..]));
+    assert!(status.contains(&format!("| Repositories | `{}` |", test_support::TEST_FOLLOW_REPO)[..]));
+    assert!(status.contains("| Webhook ids | 1234 |"));
+    assert!(status.contains("| Github rate limit | 4990/5000, resets at 00:00:00 UTC |"));
+    assert!(status.contains("| Merge queue | 0 pull request(s) |"));
+    assert!(!status.contains("bot-token") && !status.contains("owner-token") && !status.contains("nested-key") && !status.contains(test_support::TEST_WEBHOOK_SECRET));
+    assert!(!status.contains("127.0.0.1") && !status.contains(&github.url()[..]) && !status.contains("[config]"));
+}
+
+#[test]
+fn dry_run_reports_changes_instead_of_making_them() {
+
+    let github   = FakeGithub::start();
+    let tsconfig = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    tsconfig.lock().unwrap().dry_run = true;
+
+    let outbound = outbound::OutboundQueue::start(&tsconfig);
+    let handler  = commands::CommandHandler::new(&tsconfig, &outbound);
+
+    let mut event    = webhooks::WebhookEvent::new();
+    event.event_type = webhooks::WebhookEventType::IssueComment;
+    event.user       = String::from("octocat");
+    event.number     = 7;
+    event.comment_id = 42;
+    event.commands   = vec![String::from("ping")];
+    handler.parse_command(event);
+    test_support::wait_for_delivery(&outbound);
+
+    assert!(github.requests().iter().all(|request| request.method == "GET"));
+    assert!(github.comments(7).is_empty());
+
+    let mut report: Vec<(String, String)> = store::Store::new(&tsconfig).entries("dry-run.json").unwrap().iter()
+        .map(|entry| (String::from(entry.find("method").unwrap().as_str().unwrap()), String::from(entry.find("endpoint").unwrap().as_str().unwrap())))
+        .collect();
+    report.sort();
+
+    //The reply is delivered from another thread, in any order with the reactions
+    let reactions = format!("repos/{}/issues/comments/42/reactions", test_support::TEST_FOLLOW_REPO);
+    assert_eq!(report, vec![
+        (String::from("DELETE"), format!("{}/0", reactions)),
+        (String::from("POST"),   format!("repos/{}/issues/7/comments", test_support::TEST_FOLLOW_REPO)),
+        (String::from("POST"),   reactions.clone()),
+        (String::from("POST"),   reactions.clone())
+    ]);
+}
+
+#[test]
+fn dry_run_leaves_the_merge_queue_and_the_automerge_marks_alone() {
+
+    let github   = FakeGithub::start();
+    let tsconfig = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    let repo     = format!("/repos/{}", test_support::TEST_FOLLOW_REPO);
+    let head     = "0000000000000000000000000000000000000000";
+    github.respond_with("GET", &format!("{}/commits/s1/status", repo), 200, test_support::GREEN_STATUS);
+    github.respond_with("GET", &format!("{}/commits/{}/status", repo, head), 200, test_support::GREEN_STATUS);
+    github.respond_with("GET", &format!("{}/pulls/9/reviews", repo), 200, "[{\"user\": {\"login\": \"ruslo\"}, \"state\": \"APPROVED\", \"author_association\": \"OWNER\"}]");
+
+    let now      = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let entry = |number: u64| queue::QueueEntry {
+        number:      number,
+        title:       format!("PR {}", number),
+        head_sha:    String::from(head),
+        approved_by: String::from("octocat"),
+        queued_at:   0
+    };
+    let queued = queue::QueueState {
+        staging: Some(queue::Staging {entry: entry(7), sha: String::from("s1"), base_sha: String::from("m1"), started_at: now}),
+        entries: vec![entry(8)]
+    };
+    queued.save(&tsconfig).unwrap();
+    let marks: serde_json::Value = serde_json::from_str(&format!("{{\"pulls\": [{{\"number\": 9, \"method\": \"merge\", \"marked_by\": \"octocat\", \"marked_at\": 0, \"head_sha\": \"{}\"}}]}}", head)).unwrap();
+    store::Store::new(&tsconfig).save("automerge.json", &marks).unwrap();
+    tsconfig.lock().unwrap().dry_run = true;
+
+    let outbound = outbound::OutboundQueue::start(&tsconfig);
+    let handler  = commands::CommandHandler::new(&tsconfig, &outbound);
+
+    //Green CI on the staging commit and on the head of the marked PR
+    let mut status    = webhooks::WebhookEvent::new();
+    status.event_type = webhooks::WebhookEventType::Status;
+    status.user       = String::from("travis");
+    status.action     = String::from("success");
+    status.sha        = String::from("s1");
+    handler.handle_event(status.clone());
+    status.sha        = String::from(head);
+    handler.handle_event(status);
+    test_support::wait_for_delivery(&outbound);
+
+    assert!(github.requests().iter().all(|request| request.method == "GET"));
+    let reported: Vec<String> = store::Store::new(&tsconfig).entries("dry-run.json").unwrap().iter()
+        .map(|entry| String::from(entry.find("endpoint").unwrap().as_str().unwrap()))
+        .collect();
+    assert!(reported.contains(&format!("repos/{}/git/refs/heads/master", test_support::TEST_FOLLOW_REPO)));
+    assert!(reported.contains(&format!("repos/{}/pulls/9/merge", test_support::TEST_FOLLOW_REPO)));
+
+    assert_eq!(queue::QueueState::load(&tsconfig).unwrap(), queued);
+    assert_eq!(automerge::marks(&tsconfig).unwrap().iter().map(|mark| (mark.number, mark.head_sha.clone())).collect::<Vec<(u64, String)>>(), vec![(9, String::from(head))]);
+}
+
+#[test]
+fn replies_of_the_bot_come_from_the_templates() {
+
+    let github   = FakeGithub::start();
+    let tsconfig = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    {
+        let mut config = tsconfig.lock().unwrap();
+        config.set_string("config", "docs_url", "https://example.com/bot");
+        config.set_string("templates", "not_found", "No {command} here, {user}. See {docs_url}.");
+        config.set_string("templates", "available_commands", "Try {{{commands}}}.");
+    }
+
+    let outbound = outbound::OutboundQueue::start(&tsconfig);
+    let handler  = commands::CommandHandler::new(&tsconfig, &outbound);
+
+    let mut event  = webhooks::WebhookEvent::new();
+    event.user     = String::from("octocat");
+    event.number   = 7;
+    event.commands = vec![String::from("frobnicate")];
+    handler.parse_command(event);
+    test_support::wait_for_delivery(&outbound);
+
+    let comments = github.comments(7);
+    assert_eq!(comments.len(), 1);
+    assert!(comments[0].starts_with(&format!("@octocat No `frobnicate` here, octocat. See https://example.com/bot.\n\nTry {{{}}}.", test_support::available_commands(Role::Anyone))[..]));
+
+    //The command is quoted by the bot, backticks in it cannot break out of the code span
+    assert_eq!(templates::Templates::new().render("not_found", &[("command", "a` **b**")]), "Sorry the command ``a` **b**`` was not found.");
+
+    //Mistakes are found when the config is loaded
+    let invalid = |name: &str, text: &str| -> String {
+        let mut config = test_support::test_config(&github.url());
+        config.set_string("templates", name, text);
+        templates::Templates::from_config(&mut config).unwrap_err()
+    };
+    assert!(invalid("not_foud", "Nope").starts_with("There is no template named \"not_foud\""));
+    assert!(invalid("not_allowed", "Only {rolle}").starts_with("The template \"not_allowed\" cannot use {rolle}, it can use: {user}, {command}, {docs_url}, {role}."));
+    assert!(invalid("not_allowed", "Only {role").contains("is not closed"));
+    assert!(invalid("not_allowed", "Only role}").contains("is not opened"));
+}
+
+#[test]
+fn command_comments_get_reactions_with_their_status() {
+
+    let github   = FakeGithub::start();
+    let tsconfig = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    let outbound = outbound::OutboundQueue::start(&tsconfig);
+    let handler  = commands::CommandHandler::new(&tsconfig, &outbound);
+
+    let mut event    = webhooks::WebhookEvent::new();
+    event.event_type = webhooks::WebhookEventType::IssueComment;
+    event.user       = String::from("octocat");
+    event.number     = 7;
+    event.comment_id = 42;
+    event.commands   = vec![String::from("ping")];
+    handler.parse_command(event.clone());
+
+    event.comment_id = 43;
+    event.commands   = vec![String::from("ping"), String::from("frobnicate")];
+    handler.parse_command(event);
+    test_support::wait_for_delivery(&outbound);
+
+    let contents = |comment_id: u64| -> Vec<String> {
+        github.requests_to("POST", &format!("/repos/{}/issues/comments/{}/reactions", test_support::TEST_FOLLOW_REPO, comment_id)).iter()
+            .map(|request| String::from(request.body_json().find("content").and_then(|content| content.as_str()).unwrap()))
+            .collect()
+    };
+    assert_eq!(contents(42), vec!["eyes", "+1"]);
+    assert_eq!(contents(43), vec!["eyes", "confused"]);
+
+    //:eyes: is taken back once the commands ran
+    let removed = github.requests().into_iter()
+        .filter(|request| request.method == "DELETE" && request.path.contains("/reactions/"))
+        .count();
+    assert_eq!(removed, 2);
+}
+
+//A command defined outside of commands.rs, with its own settings
+struct Greet {
+    greeting: String
+}
+
+impl commands::BotCommand for Greet {
+    fn run(&self, context: &mut commands::CommandContext) -> Result<response::Message, String> {
+        context.replies.send(response::Message::text("Working on it"));
+        let repo = context.config.get_string_required("config", "github_follow_repo");
+        Ok(response::Message::text(&format!("{} {} from {}", self.greeting, context.args.positional.join(" "), repo)))
+    }
+}
+
+#[test]
+fn commands_can_be_registered_as_structs() {
+
+    let github   = FakeGithub::start();
+    let tsconfig = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    let outbound = outbound::OutboundQueue::start(&tsconfig);
+
+    let mut handler = commands::CommandHandler::new(&tsconfig, &outbound);
+    handler.register("greet", commands::Command::new(false, Role::Anyone, Greet{greeting: String::from("Hello")})
+        .summary("Greets people.")
+        .usage("greet <name>..."));
+
+    let mut event  = webhooks::WebhookEvent::new();
+    event.user     = String::from("octocat");
+    event.number   = 7;
+    event.commands = vec![String::from("greet Alice Bob")];
+    handler.parse_command(event.clone());
+
+    event.commands = vec![String::from("help")];
+    handler.parse_command(event);
+    test_support::wait_for_delivery(&outbound);
+
+    let comments = github.comments(7);
+    assert_eq!(comments.len(), 3);
+    assert_eq!(comments[0], "@octocat Working on it");
+    assert_eq!(comments[1], format!("@octocat Hello Alice Bob from {}", test_support::TEST_FOLLOW_REPO));
+    assert!(comments[2].contains("| `greet` |  | `greet <name>...` | Greets people. |"));
+}
+
+//Reports progress until it is cancelled
+struct Crunch;
+
+impl commands::BotCommand for Crunch {
+    fn run(&self, context: &mut commands::CommandContext) -> Result<response::Message, String> {
+        let mut step = 0;
+        while !context.is_cancelled() {
+            step += 1;
+            context.progress(&format!("Crunched {} number(s)", step));
+            thread::sleep(time::Duration::from_millis(100));
+        }
+        Ok(response::Message::text("Stopped"))
+    }
+}
+
+#[test]
+fn background_jobs_report_progress_and_can_be_cancelled() {
+
+    let github   = FakeGithub::start();
+    let tsconfig = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    let outbound = outbound::OutboundQueue::start(&tsconfig);
+
+    let mut handler = commands::CommandHandler::new(&tsconfig, &outbound);
+    handler.register("crunch", commands::Command::new(false, Role::Anyone, Crunch).background(60));
+
+    let mut event  = webhooks::WebhookEvent::new();
+    event.user     = String::from("octocat");
+    event.number   = 7;
+    event.commands = vec![String::from("crunch")];
+    handler.parse_command(event.clone());
+    github.wait_for_comment(7, "Job `1` is running: `crunch`");
+
+    event.commands = vec![String::from("jobs")];
+    handler.parse_command(event.clone());
+    assert!(github.wait_for_comment(7, "job(s) running")[1].contains("| 1 | `crunch` | octocat | #7 |"));
+
+    //Only the user who started the job or whitelisted users can cancel it
+    let mut other  = event.clone();
+    other.user     = String::from("someone-else");
+    other.commands = vec![String::from("cancel 1")];
+    handler.parse_command(other);
+    github.wait_for_comment(7, "Only @octocat or whitelisted users can cancel job `1`.");
+
+    event.commands = vec![String::from("cancel 1")];
+    handler.parse_command(event.clone());
+    let comments = github.wait_for_comment(7, "Job `1` was cancelled: `crunch`");
+    assert!(comments[0].contains("Cancelled by @octocat."));
+    assert!(comments[0].contains(&sticky::marker("job-1")));
+
+    //The timeout can be changed in the config
+    tsconfig.lock().unwrap().set_string("timeouts", "crunch", "1");
+    event.commands = vec![String::from("please crunch")];
+    handler.parse_command(event);
+    github.wait_for_comment(7, "You didn't need to say please");
+    github.wait_for_comment(7, "Job `2` timed out: `crunch`");
+
+    //Jobs are audited as started, their outcome is only known later
+    let entries = audit::entries(&tsconfig, None, None).unwrap();
+    assert_eq!((&entries[0].command[..], &entries[0].status[..]), ("crunch", "Started"));
+}
+
+#[test]
+fn moderation_commands_change_the_issue() {
+
+    let github     = FakeGithub::start();
+    let tsconfig   = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    let repo       = format!("/repos/{}", test_support::TEST_FOLLOW_REPO);
+    let permission = format!("{}/collaborators/octocat/permission", repo);
+    github.respond_with("GET",   &permission, 200, "{\"permission\": \"write\", \"role_name\": \"maintain\"}");
+    github.respond_with("GET",   &format!("{}/labels", repo), 200, "[{\"name\": \"bug\"}, {\"name\": \"help wanted\"}]");
+    github.respond_with("PATCH", &format!("{}/issues/7", repo), 200, "{\"number\": 7}");
+    github.respond_with("PUT",   &format!("{}/issues/7/lock", repo), 204, "");
+    github.respond_with("POST",  &format!("{}/issues/7/assignees", repo), 201, "{\"assignees\": [{\"login\": \"OctoCat\"}]}");
+
+    let outbound = outbound::OutboundQueue::start(&tsconfig);
+    let handler  = commands::CommandHandler::new(&tsconfig, &outbound);
+
+    let mut event  = webhooks::WebhookEvent::new();
+    event.user     = String::from("octocat");
+    event.number   = 7;
+    event.commands = vec![
+        String::from("label add BUG \"help wanted\""),
+        String::from("label add wontfix"),
+        String::from("close --reason not-planned Duplicate of #12"),
+        String::from("lock too-heated"),
+        String::from("lock off-topic"),
+        String::from("assign me ghost")
+    ];
+    handler.parse_command(event);
+    test_support::wait_for_delivery(&outbound);
+
+    let reply = &github.comments(7)[0];
+    assert!(reply.contains("Added `bug`, `help wanted`."));
+    assert!(reply.contains("The repository has no label `wontfix`. Its labels are: `bug`, `help wanted`."));
+    assert!(reply.contains("Closed as not planned.\n\n> Duplicate of #12"));
+    assert!(reply.contains("Locked as too heated."));
+    assert!(reply.contains("Locked as off-topic."));
+    assert!(reply.contains("Assigned `octocat`.\n\nGithub did not assign `ghost`"));
+
+    //Labels are sent as spelled in the repository
+    let labels = github.requests_to("POST", &format!("{}/issues/7/labels", repo));
+    assert_eq!(labels.len(), 1);
+    assert_eq!(labels[0].body_json(), serde_json::from_str::<serde_json::Value>("[\"bug\", \"help wanted\"]").unwrap());
+
+    let state = github.requests_to("PATCH", &format!("{}/issues/7", repo))[0].body_json();
+    assert_eq!(state.find("state").and_then(|state| state.as_str()), Some("closed"));
+    assert_eq!(state.find("state_reason").and_then(|reason| reason.as_str()), Some("not_planned"));
+
+    //Github spells the reasons its own way
+    let locks: Vec<String> = github.requests_to("PUT", &format!("{}/issues/7/lock", repo)).iter()
+        .map(|lock| String::from(lock.body_json().find("lock_reason").and_then(|reason| reason.as_str()).unwrap_or("")))
+        .collect();
+    assert_eq!(locks, vec!["too heated", "off-topic"]);
+
+    //Users without the role are turned down before Github is called
+    let mut newcomer  = webhooks::WebhookEvent::new();
+    newcomer.user     = String::from("newcomer");
+    newcomer.number   = 8;
+    newcomer.commands = vec![String::from("unlock")];
+    handler.parse_command(newcomer);
+    test_support::wait_for_delivery(&outbound);
+    assert!(github.comments(8)[0].contains("Sorry! You need to be a collaborator with write permission to run that command."));
+    assert!(github.requests_to("DELETE", &format!("{}/issues/7/lock", repo)).is_empty());
+}
+
+#[test]
+fn merge_checks_the_pull_request_before_merging_it() {
+
+    let github     = FakeGithub::start();
+    let tsconfig   = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    let repo       = format!("/repos/{}", test_support::TEST_FOLLOW_REPO);
+    let pull       = "{\"number\": 7, \"title\": \"Add Boost 1.62\", \"body\": \"Fixes #3\", \"state\": \"open\", \"merged\": false, \"draft\": true, \"mergeable\": false, \"user\": {\"login\": \"newcomer\"}, \"head\": {\"sha\": \"abc\"}, \"base\": {\"ref\": \"master\"}}";
+    let permission = format!("{}/collaborators/octocat/permission", repo);
+    github.respond_with("GET", &permission, 200, "{\"permission\": \"write\", \"role_name\": \"write\"}");
+    github.respond_with("GET", &format!("{}/pulls/7", repo), 200, pull);
+    github.respond_with("GET", &format!("{}/commits/abc/status", repo), 200, test_support::GREEN_STATUS);
+    github.respond_with("GET", &format!("{}/commits/abc/check-runs", repo), 200, "{\"total_count\": 2, \"check_runs\": [{\"name\": \"build\", \"status\": \"completed\", \"conclusion\": \"failure\"}, {\"name\": \"lint\", \"status\": \"in_progress\", \"conclusion\": null}]}");
+
+    let outbound = outbound::OutboundQueue::start(&tsconfig);
+    let handler  = commands::CommandHandler::new(&tsconfig, &outbound);
+
+    let mut event  = webhooks::WebhookEvent::new();
+    event.user     = String::from("octocat");
+    event.number   = 7;
+    event.commands = vec![String::from("merge squash")];
+    handler.parse_command(event.clone());
+    test_support::wait_for_delivery(&outbound);
+
+    let blocked = &github.comments(7)[0];
+    assert!(blocked.contains("#7 cannot be merged yet:\n\n* [x] The pull request is open\n* [ ] The pull request is not a draft\n* [ ] Approved by 0 reviewer(s), 1 needed (pending)\n"));
+    assert!(blocked.contains("* [x] Commit statuses are green\n* [ ] Check runs are green, failing: `build`\n* [ ] No conflicts with the base branch"));
+    assert!(github.requests_to("PUT", &format!("{}/pulls/7/merge", repo)).is_empty());
+
+    //Approvals of users who are not collaborators do not count
+    github.respond_with("GET", &format!("{}/pulls/7", repo), 200, &pull.replace("\"draft\": true, \"mergeable\": false", "\"draft\": false, \"mergeable\": true"));
+    github.respond_with("GET", &format!("{}/commits/abc/check-runs", repo), 200, "{\"total_count\": 1, \"check_runs\": [{\"name\": \"build\", \"status\": \"completed\", \"conclusion\": \"success\"}]}");
+    github.respond_with("GET", &format!("{}/pulls/7/reviews", repo), 200, "[{\"user\": {\"login\": \"drive-by\"}, \"state\": \"APPROVED\", \"author_association\": \"NONE\"}, {\"user\": {\"login\": \"newcomer\"}, \"state\": \"APPROVED\", \"author_association\": \"CONTRIBUTOR\"}]");
+    handler.parse_command(event.clone());
+    test_support::wait_for_delivery(&outbound);
+
+    assert!(github.comments(7)[1].contains("* [ ] Approved by 0 reviewer(s), 1 needed (pending)"));
+    assert!(github.requests_to("PUT", &format!("{}/pulls/7/merge", repo)).is_empty());
+
+    //The last review of a reviewer is the one that counts
+    github.respond_with("GET", &format!("{}/pulls/7/reviews", repo), 200, "[{\"user\": {\"login\": \"ruslo\"}, \"state\": \"CHANGES_REQUESTED\", \"author_association\": \"OWNER\"}, {\"user\": {\"login\": \"ruslo\"}, \"state\": \"APPROVED\", \"author_association\": \"OWNER\"}, {\"user\": {\"login\": \"newcomer\"}, \"state\": \"COMMENTED\", \"author_association\": \"CONTRIBUTOR\"}]");
+    handler.parse_command(event);
+    test_support::wait_for_delivery(&outbound);
+
+    assert!(github.comments(7)[2].contains("Merged #7 into `master` with `squash` (1111111111111111111111111111111111111111)."));
+    let merge = github.requests_to("PUT", &format!("{}/pulls/7/merge", repo))[0].body_json();
+    assert_eq!(merge.find("sha").and_then(|sha| sha.as_str()), Some("abc"));
+    assert_eq!(merge.find("merge_method").and_then(|method| method.as_str()), Some("squash"));
+    assert_eq!(merge.find("commit_title").and_then(|title| title.as_str()), Some("Add Boost 1.62 (#7)"));
+    assert_eq!(merge.find("commit_message").and_then(|message| message.as_str()), Some("Fixes #3\n\nApproved-by: ruslo\nMerged-by: octocat"));
+}
+
+#[test]
+fn automerge_merges_marked_pull_requests_once_they_are_ready() {
+
+    let github     = FakeGithub::start();
+    let tsconfig   = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    let repo       = format!("/repos/{}", test_support::TEST_FOLLOW_REPO);
+    let permission = format!("{}/collaborators/octocat/permission", repo);
+    github.respond_with("GET", &permission, 200, "{\"permission\": \"write\", \"role_name\": \"maintain\"}");
+    github.respond_with("GET", &format!("{}/pulls/7", repo), 200, "{\"number\": 7, \"title\": \"Add Boost 1.62\", \"state\": \"open\", \"mergeable\": true, \"head\": {\"sha\": \"abc\"}, \"base\": {\"ref\": \"master\"}}");
+    github.respond_with("GET", &format!("{}/commits/abc/status", repo), 200, test_support::GREEN_STATUS);
+
+    let outbound = outbound::OutboundQueue::start(&tsconfig);
+    let handler  = commands::CommandHandler::new(&tsconfig, &outbound);
+
+    let mut command  = webhooks::WebhookEvent::new();
+    command.user     = String::from("octocat");
+    command.number   = 7;
+    command.commands = vec![String::from("automerge squash")];
+    handler.parse_command(command.clone());
+    test_support::wait_for_delivery(&outbound);
+    assert!(github.comments(7)[0].contains("I will merge #7 with `squash` once it is ready:"));
+    assert_eq!(automerge::marks(&tsconfig).unwrap().iter().map(|mark| (mark.number, mark.method.clone())).collect::<Vec<(u64, String)>>(), vec![(7, String::from("squash"))]);
+
+    //A status of another commit does not make the PR ready
+    let mut status    = webhooks::WebhookEvent::new();
+    status.event_type = webhooks::WebhookEventType::Status;
+    status.user       = String::from("travis");
+    status.action     = String::from("success");
+    status.sha        = String::from("def");
+    let lookups       = github.requests_to("GET", &format!("{}/pulls/7", repo)).len();
+    handler.handle_event(status);
+    assert_eq!(github.requests_to("GET", &format!("{}/pulls/7", repo)).len(), lookups);
+    assert!(github.requests_to("PUT", &format!("{}/pulls/7/merge", repo)).is_empty());
+
+    //The approval is the last condition
+    github.respond_with("GET", &format!("{}/pulls/7/reviews", repo), 200, "[{\"user\": {\"login\": \"ruslo\"}, \"state\": \"APPROVED\", \"author_association\": \"OWNER\"}]");
+    let mut review    = webhooks::WebhookEvent::new();
+    review.event_type = webhooks::WebhookEventType::PullRequestReview;
+    review.user       = String::from("ruslo");
+    review.action     = String::from("submitted");
+    review.number     = 7;
+    review.sha        = String::from("abc");
+    handler.handle_event(review);
+    test_support::wait_for_delivery(&outbound);
+
+    assert!(github.comments(7)[1].starts_with("@octocat Merged #7 automatically into `master` with `squash`"));
+    assert_eq!(github.requests_to("PUT", &format!("{}/pulls/7/merge", repo))[0].body_json().find("merge_method").and_then(|method| method.as_str()), Some("squash"));
+    assert!(automerge::marks(&tsconfig).unwrap().is_empty());
+
+    //A push from someone who is not a maintainer removes the mark
+    command.number = 8;
+    command.commands = vec![String::from("automerge")];
+    handler.parse_command(command);
+    test_support::wait_for_delivery(&outbound);
+    assert_eq!(automerge::marks(&tsconfig).unwrap().len(), 1);
+
+    let mut push    = webhooks::WebhookEvent::new();
+    push.event_type = webhooks::WebhookEventType::PullRequest;
+    push.user       = String::from("newcomer");
+    push.action     = String::from("synchronize");
+    push.number     = 8;
+    handler.handle_event(push);
+    test_support::wait_for_delivery(&outbound);
+
+    assert!(github.comments(8)[1].contains("Gave up merging #8 automatically, @newcomer pushed new commits and is not a maintainer."));
+    assert!(automerge::marks(&tsconfig).unwrap().is_empty());
+    assert!(github.requests_to("PUT", &format!("{}/pulls/8/merge", repo)).is_empty());
+}
+
+#[test]
+fn pull_request_and_ci_events_are_queued() {
+
+    let github     = FakeGithub::start();
+    let tsconfig   = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    let (tx, rx)   = channel();
+    let mut server = test_support::start_webhook_server(&tsconfig, tx);
+
+    test_support::send_webhook(&server.socket, "status", "{\"sha\": \"abc\", \"state\": \"success\", \"sender\": {\"login\": \"travis\"}}");
+    let status = rx.try_recv().unwrap();
+    assert_eq!((&status.sha[..], &status.action[..], &status.user[..]), ("abc", "success", "travis"));
+
+    test_support::send_webhook(&server.socket, "pull_request", "{\"action\": \"synchronize\", \"sender\": {\"login\": \"newcomer\"}, \"pull_request\": {\"number\": 8, \"id\": 100008, \"user\": {\"login\": \"newcomer\"}, \"head\": {\"sha\": \"def\"}}}");
+    let push = rx.try_recv().unwrap();
+    assert_eq!((push.number, &push.sha[..], &push.action[..], &push.issue_author[..]), (8, "def", "synchronize", "newcomer"));
+
+    let _ = server.close();
+}
+
+#[test]
+fn merge_queue_tests_pull_requests_one_at_a_time() {
+
+    let github     = FakeGithub::start();
+    let tsconfig   = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    let repo       = format!("/repos/{}", test_support::TEST_FOLLOW_REPO);
+    let permission = format!("{}/collaborators/octocat/permission", repo);
+    github.respond_with("GET",   &permission, 200, "{\"permission\": \"write\", \"role_name\": \"write\"}");
+    github.respond_with("GET",   &format!("{}/pulls/7", repo), 200, "{\"number\": 7, \"title\": \"Add Boost 1.62\", \"state\": \"open\", \"head\": {\"sha\": \"abc\"}}");
+    github.respond_with("GET",   &format!("{}/pulls/8", repo), 200, "{\"number\": 8, \"title\": \"Add GTest 1.8\", \"state\": \"open\", \"head\": {\"sha\": \"def\"}}");
+    github.respond_with("GET",   &format!("{}/git/refs/heads/master", repo), 200, "{\"object\": {\"sha\": \"m1\"}}");
+    github.respond_with("PATCH", &format!("{}/git/refs/heads/staging", repo), 200, "{}");
+    github.respond_with("PATCH", &format!("{}/git/refs/heads/master", repo), 200, "{}");
+    github.respond_with("POST",  &format!("{}/merges", repo), 201, "{\"sha\": \"s1\"}");
+
+    let outbound = outbound::OutboundQueue::start(&tsconfig);
+    let handler  = commands::CommandHandler::new(&tsconfig, &outbound);
+
+    let mut command  = webhooks::WebhookEvent::new();
+    command.user     = String::from("octocat");
+    command.number   = 7;
+    command.commands = vec![String::from("r+")];
+    handler.parse_command(command.clone());
+    command.number   = 8;
+    handler.parse_command(command.clone());
+    test_support::wait_for_delivery(&outbound);
+
+    let first = github.comments(7);
+    assert!(first[0].contains("Added #7 (`abc`) to the merge queue at position 1."));
+    assert!(first[1].contains("Testing #7 on top of `master` in `staging` (`s1`)."));
+    assert!(github.comments(8)[0].contains("Added #8 (`def`) to the merge queue at position 2."));
+
+    command.number   = 9;
+    command.commands = vec![String::from("queue")];
+    handler.parse_command(command);
+    test_support::wait_for_delivery(&outbound);
+    let queue = &github.comments(9)[0];
+    assert!(queue.contains("| 1 | #7 Add Boost 1.62 | octocat | `abc` | Testing `s1` |"));
+    assert!(queue.contains("| 2 | #8 Add GTest 1.8 | octocat | `def` | Waiting |"));
+
+    //A check suite finishing before any commit status is posted does not merge untested code
+    let mut suite    = webhooks::WebhookEvent::new();
+    suite.event_type = webhooks::WebhookEventType::CheckSuite;
+    suite.user       = String::from("github-actions");
+    suite.action     = String::from("completed");
+    suite.sha        = String::from("s1");
+    handler.handle_event(suite);
+    assert!(github.requests_to("PATCH", &format!("{}/git/refs/heads/master", repo)).is_empty());
+
+    //Green CI on the staging commit merges it, the next PR is staged
+    github.respond_with("GET",  &format!("{}/commits/s1/status", repo), 200, test_support::GREEN_STATUS);
+    github.respond_with("POST", &format!("{}/merges", repo), 201, "{\"sha\": \"s2\"}");
+    let mut status    = webhooks::WebhookEvent::new();
+    status.event_type = webhooks::WebhookEventType::Status;
+    status.user       = String::from("travis");
+    status.action     = String::from("success");
+    status.sha        = String::from("s1");
+    handler.handle_event(status.clone());
+    test_support::wait_for_delivery(&outbound);
+
+    assert!(github.comments(7)[2].contains("Merged #7 into `master` (`s1`), CI was green on top of it."));
+    assert_eq!(github.requests_to("PATCH", &format!("{}/git/refs/heads/master", repo))[0].body_json().find("sha").and_then(|sha| sha.as_str()), Some("s1"));
+    assert!(github.comments(8)[1].contains("Testing #8 on top of `master` in `staging` (`s2`)."));
+    let merges = github.requests_to("POST", &format!("{}/merges", repo));
+    assert_eq!(merges.iter().map(|merge| String::from(merge.body_json().find("head").unwrap().as_str().unwrap())).collect::<Vec<String>>(), vec!["abc", "def"]);
+
+    //Red CI reports the failure and leaves the base branch alone
+    github.respond_with("GET", &format!("{}/commits/s2/status", repo), 200, "{\"state\": \"failure\", \"statuses\": [{\"context\": \"travis\", \"state\": \"failure\"}]}");
+    status.action = String::from("failure");
+    status.sha    = String::from("s2");
+    handler.handle_event(status);
+    test_support::wait_for_delivery(&outbound);
+
+    assert!(github.comments(8)[2].contains("#8 failed on top of `master`, took it out of the merge queue:\n\n* Commit statuses are green, failing: `travis`"));
+    assert_eq!(github.requests_to("PATCH", &format!("{}/git/refs/heads/master", repo)).len(), 1);
+    assert_eq!(queue::QueueState::load(&tsconfig).unwrap(), queue::QueueState {staging: None, entries: Vec::new()});
+
+    //A refused update of a base branch that did not move is reported, not retried
+    github.respond_with("PATCH", &format!("{}/git/refs/heads/master", repo), 422, "{\"message\": \"Required status check \\\"jenkins\\\" is expected.\"}");
+    github.respond_with("POST",  &format!("{}/merges", repo), 201, "{\"sha\": \"s3\"}");
+    command.number   = 7;
+    command.commands = vec![String::from("r+")];
+    handler.parse_command(command.clone());
+    test_support::wait_for_delivery(&outbound);
+    github.respond_with("GET", &format!("{}/commits/s3/status", repo), 200, test_support::GREEN_STATUS);
+    status.action = String::from("success");
+    status.sha    = String::from("s3");
+    handler.handle_event(status.clone());
+    test_support::wait_for_delivery(&outbound);
+
+    assert!(github.comments(7)[5].contains("Failed to merge #7 into `master`, took it out of the merge queue: updating `master` was refused: Required status check \"jenkins\" is expected."));
+    assert_eq!(queue::QueueState::load(&tsconfig).unwrap(), queue::QueueState {staging: None, entries: Vec::new()});
+
+    //When the base branch moved, the PR is tested again on top of it
+    github.respond_with("POST", &format!("{}/merges", repo), 201, "{\"sha\": \"s4\"}");
+    handler.parse_command(command);
+    test_support::wait_for_delivery(&outbound);
+    github.respond_with("GET",  &format!("{}/git/refs/heads/master", repo), 200, "{\"object\": {\"sha\": \"m2\"}}");
+    github.respond_with("POST", &format!("{}/merges", repo), 201, "{\"sha\": \"s5\"}");
+    github.respond_with("GET",  &format!("{}/commits/s4/status", repo), 200, test_support::GREEN_STATUS);
+    status.sha = String::from("s4");
+    handler.handle_event(status);
+    test_support::wait_for_delivery(&outbound);
+
+    assert!(github.comments(7)[8].contains("Testing #7 on top of `master` in `staging` (`s5`)."));
+    let staged = queue::QueueState::load(&tsconfig).unwrap().staging.unwrap();
+    assert_eq!((&staged.sha[..], &staged.base_sha[..]), ("s5", "m2"));
+}
+
+#[test]
+fn merge_queue_drops_a_pull_request_ci_does_not_report_on() {
+
+    let github   = FakeGithub::start();
+    let tsconfig = Arc::new(Mutex::new(test_support::test_config(&github.url())));
+    tsconfig.lock().unwrap().set_string("config", "queue_timeout", "3600");
+
+    let entry = queue::QueueEntry {
+        number:      7,
+        title:       String::from("Add Boost 1.62"),
+        head_sha:    String::from("abc"),
+        approved_by: String::from("octocat"),
+        queued_at:   0
+    };
+    queue::QueueState {
+        staging: Some(queue::Staging {entry: entry, sha: String::from("s1"), base_sha: String::from("m1"), started_at: 0}),
+        entries: Vec::new()
+    }.save(&tsconfig).unwrap();
+
+    let outbound = outbound::OutboundQueue::start(&tsconfig);
+    let handler  = commands::CommandHandler::new(&tsconfig, &outbound);
+
+    let mut command  = webhooks::WebhookEvent::new();
+    command.user     = String::from("octocat");
+    command.number   = 9;
+    command.commands = vec![String::from("queue")];
+    handler.parse_command(command);
+    test_support::wait_for_delivery(&outbound);
+
+    assert!(github.comments(7)[0].contains("CI did not report on `s1` within 3600 seconds, took #7 out of the merge queue."));
+    assert!(github.comments(9)[0].contains("The merge queue is empty."));
+    assert_eq!(queue::QueueState::load(&tsconfig).unwrap(), queue::QueueState {staging: None, entries: Vec::new()});
+}
@@ -15,6 +15,7 @@ use hyper::client::IntoUrl;
 use hyper::header::Headers;
 
 extern crate iron;
+use self::iron::method::Method;
 use self::iron::middleware;
 use self::iron::prelude::*;
 use self::iron::status;
@@ -26,14 +27,14 @@ use self::openssl::crypto::hmac::hmac;
 extern crate rand;
 use self::rand::Rng;
 
-extern crate regex;
-use self::regex::Regex;
-
 extern crate serde;
 extern crate serde_json;
 
+use archive;
 use commands;
 use config;
+use mentions;
+use outbound;
 
 include!("logger_macros.rs");
 
@@ -71,6 +72,19 @@ impl middleware::Handler for WebhookHandler {
         let config   = self.config.clone();
         let queue_tx = self.queue_tx.clone();
 
+        //Archived responses are served from the same server, i.e /archive/{id}
+        if request.method == Method::Get && request.url.path.len() == 2 && request.url.path[0] == "archive" {
+            thread_trace!("  Serve archive entry");
+            return match archive::load(&config, &request.url.path[1]) {
+                Ok(Some(content)) => Ok(Response::with((status::Ok, content))),
+                Ok(None)          => Ok(Response::with((status::NotFound, "Not found."))),
+                Err(err)          => {
+                    thread_error!("{}", err);
+                    Ok(Response::with((status::InternalServerError, "Failed to read the archive.")))
+                }
+            }
+        }
+
         //Get body
         thread_trace!("  Extract request body");
         let mut body_string: String  = String::new();
@@ -106,6 +120,9 @@ impl middleware::Handler for WebhookHandler {
             }
         }
 
+        //Get X-GitHub-Delivery header value, identifies the delivery in the audit log
+        let delivery_string = extract_header_string(&request.headers, "X-GitHub-Delivery").unwrap_or(String::new());
+
         //Parse the body
         let body_value: serde_json::Value;
         match serde_json::from_str(&body_string[..]) {
@@ -125,10 +142,11 @@ impl middleware::Handler for WebhookHandler {
                 match WebhookEvent::from_issue_json(&config, &body_value.as_object().unwrap()) {
                     Ok(webhook_event_option) => {
                         match webhook_event_option {
-                            Some(webhook_event) => {
+                            Some(mut webhook_event) => {
+                                webhook_event.delivery = delivery_string;
                                 queue_tx.lock().unwrap().send(webhook_event).unwrap();
                             }
-                            None                => return Ok(Response::with((status::Ok, "Skipped.")))
+                            None                    => return Ok(Response::with((status::Ok, "Skipped.")))
                         }
                     }
                     Err(err)                 => {
@@ -141,8 +159,11 @@ impl middleware::Handler for WebhookHandler {
                 match WebhookEvent::from_pull_request_json(&config, &body_value.as_object().unwrap()) {
                     Ok(webhook_event_option) => {
                         match webhook_event_option {
-                            Some(webhook_event) => {queue_tx.lock().unwrap().send(webhook_event).unwrap();}
-                            None                => return Ok(Response::with((status::Ok, "Skipped.")))
+                            Some(mut webhook_event) => {
+                                webhook_event.delivery = delivery_string;
+                                queue_tx.lock().unwrap().send(webhook_event).unwrap();
+                            }
+                            None                    => return Ok(Response::with((status::Ok, "Skipped.")))
                         }
                     }
                     Err(err)                 => {
@@ -151,6 +172,21 @@ impl middleware::Handler for WebhookHandler {
                     }
                 }
             }
+            WebhookEventType::PullRequest        |
+            WebhookEventType::PullRequestReview  |
+            WebhookEventType::Status             |
+            WebhookEventType::CheckSuite         => {
+                match WebhookEvent::from_activity_json(webhook_event_type.clone(), &body_value.as_object().unwrap()) {
+                    Ok(mut webhook_event) => {
+                        webhook_event.delivery = delivery_string;
+                        queue_tx.lock().unwrap().send(webhook_event).unwrap();
+                    }
+                    Err(err)              => {
+                        thread_error!("{}", format!("Failed to parse the request body data in a github webhook: {}.", err));
+                        return Ok(Response::with((status::InternalServerError, format!("Failed to parse the request body data: {}.", err))))
+                    }
+                }
+            }
             WebhookEventType::Invalid            => {
                 return Ok(Response::with((status::BadRequest, "Invalid event.")))
             }
@@ -169,6 +205,10 @@ pub enum WebhookEventType {
     Ping,
     IssueComment,
     PullRequestComment,
+    PullRequest,
+    PullRequestReview,
+    Status,
+    CheckSuite,
     Invalid
 }
 
@@ -179,6 +219,10 @@ impl WebhookEventType {
             "ping"                        => WebhookEventType::Ping,
             "issue_comment"               => WebhookEventType::IssueComment,
             "pull_request_review_comment" => WebhookEventType::PullRequestComment,
+            "pull_request"                => WebhookEventType::PullRequest,
+            "pull_request_review"         => WebhookEventType::PullRequestReview,
+            "status"                      => WebhookEventType::Status,
+            "check_suite"                 => WebhookEventType::CheckSuite,
             _                             => WebhookEventType::Invalid
         }
     }
@@ -193,15 +237,25 @@ impl WebhookEventType {
 /// event_type: Type of the event (issue_comment, ping, ...)
 /// number:     Issue or PR number
 /// id:         Github ID for Issue or PR
-/// user:       User that triggered the event
-/// command:    Command made by user
+/// comment_id:   Github ID of the comment that triggered the event
+/// user:         User that triggered the event
+/// issue_author: User that opened the issue or PR
+/// commands:     Commands made by user, one per mention of the bot
+/// delivery:     Github ID of the webhook delivery (X-GitHub-Delivery), empty if unknown
+/// action:       What happened, i.e "synchronize" for a push to a PR or the state of a status
+/// sha:          Commit the event is about, the head of the PR for PR events
 #[derive(Clone, Debug)]
 pub struct WebhookEvent {
-    pub event_type: WebhookEventType,
-    pub number:     u64,
-    pub id:         u64,
-    pub user:       String,
-    pub command:    String
+    pub event_type:   WebhookEventType,
+    pub number:       u64,
+    pub id:           u64,
+    pub comment_id:   u64,
+    pub user:         String,
+    pub issue_author: String,
+    pub commands:     Vec<String>,
+    pub delivery:     String,
+    pub action:       String,
+    pub sha:          String
 }
 
 impl WebhookEvent {
@@ -209,11 +263,16 @@ impl WebhookEvent {
     pub fn new() -> WebhookEvent {
         thread_trace!("webhook.rs: WebhookEvent::new()");
         WebhookEvent{
-            event_type: WebhookEventType::Invalid,
-            number:     0,
-            id:         0,
-            user:       String::new(),
-            command:    String::new()
+            event_type:   WebhookEventType::Invalid,
+            number:       0,
+            id:           0,
+            comment_id:   0,
+            user:         String::new(),
+            issue_author: String::new(),
+            commands:     Vec::new(),
+            delivery:     String::new(),
+            action:       String::new(),
+            sha:          String::new()
         }
     }
 
@@ -244,16 +303,19 @@ impl WebhookEvent {
 
         //Get "comment" Object
         let comment_object = try!(extract_json_object_named(&json_object, "comment"));
+        event.comment_id   = try!(extract_json_u64(&comment_object, "id"));
 
-        //Check if the bot was mentioned, i.e if the message is directed towards the bot
+        //Check if the bot was mentioned or the prefix used, i.e if the message is directed towards the bot
         let github_bot_name     = config.get_string_required("config", "github_bot_name");
+        let repo                = event_repo(&mut config, &json_object);
+        let command_prefix      = mentions::command_prefix(&mut config, &repo);
         let comment_body_string = try!(extract_json_string(&comment_object, "body"));
-        let regex               = Regex::new(&format!("@{}", github_bot_name)[..]).unwrap();
+        let commands            = mentions::extract_commands(&comment_body_string, &github_bot_name, command_prefix.as_ref().map(|prefix| &prefix[..]));
 
         thread_trace!("  Is bot mentioned test");
-        if regex.find(&comment_body_string[..]).is_some() {
+        if !commands.is_empty() {
             thread_trace!("    true");
-            event.command = String::from(regex.replace(&comment_body_string[..], "").trim());
+            event.commands = commands;
         } else {
             thread_trace!("    false");
             thread_trace!("Return Ok(None)");
@@ -269,6 +331,10 @@ impl WebhookEvent {
         event.number     = try!(extract_json_u64(&issue_object, "number"));
         event.id         = try!(extract_json_u64(&issue_object, "id"));
 
+        //Get the issue author
+        let author_object  = try!(extract_json_object_named(&issue_object, "user"));
+        event.issue_author = try!(extract_json_string(&author_object, "login"));
+
         thread_trace!("Return Ok");
         Ok(Option::Some(event))
     }
@@ -300,16 +366,19 @@ impl WebhookEvent {
 
         //Get "comment" Object
         let comment_object = try!(extract_json_object_named(&json_object, "comment"));
+        event.comment_id   = try!(extract_json_u64(&comment_object, "id"));
 
-        //Check if the bot was mentioned, i.e if the message is directed towards the bot
+        //Check if the bot was mentioned or the prefix used, i.e if the message is directed towards the bot
         let github_bot_name     = config.get_string_required("config", "github_bot_name");
+        let repo                = event_repo(&mut config, &json_object);
+        let command_prefix      = mentions::command_prefix(&mut config, &repo);
         let comment_body_string = try!(extract_json_string(&comment_object, "body"));
-        let regex               = Regex::new(&format!("@{}", github_bot_name)[..]).unwrap();
+        let commands            = mentions::extract_commands(&comment_body_string, &github_bot_name, command_prefix.as_ref().map(|prefix| &prefix[..]));
 
         thread_trace!("  Is bot mentioned test");
-        if regex.find(&comment_body_string[..]).is_some() {
+        if !commands.is_empty() {
             thread_trace!("    true");
-            event.command = String::from(regex.replace(&comment_body_string[..], "").trim());
+            event.commands = commands;
         } else {
             thread_trace!("    false");
             thread_trace!("Return Ok(None)");
@@ -325,9 +394,55 @@ impl WebhookEvent {
         event.number            = try!(extract_json_u64(&pull_request_object, "number"));
         event.id                = try!(extract_json_u64(&pull_request_object, "id"));
 
+        //Get the PR author
+        let author_object  = try!(extract_json_object_named(&pull_request_object, "user"));
+        event.issue_author = try!(extract_json_string(&author_object, "login"));
+
         thread_trace!("Return Ok");
         Ok(Option::Some(event))
     }
+
+    ///Events that are not commands, they let the bot follow PRs and CI.
+    ///pull_request, pull_request_review: number, id, author and head of the PR
+    ///status, check_suite:               sha of the commit, number is 0
+    ///Err: An error occurred
+    pub fn from_activity_json(event_type: WebhookEventType, json_object: &BTreeMap<String, serde_json::Value>) -> Result<WebhookEvent, String> {
+
+        thread_trace!("webhook.rs: WebhookEvent::from_activity_json({:?}, json_object)", event_type);
+
+        let mut event    = WebhookEvent::new();
+        event.event_type = event_type.clone();
+
+        //Get "sender" string, the user who pushed, reviewed or ran CI
+        let sender_object = try!(extract_json_object_named(&json_object, "sender"));
+        event.user        = try!(extract_json_string(&sender_object, "login"));
+
+        match event_type {
+            WebhookEventType::Status     => {
+                event.action = try!(extract_json_string(&json_object, "state"));
+                event.sha    = try!(extract_json_string(&json_object, "sha"));
+            }
+            WebhookEventType::CheckSuite => {
+                event.action      = try!(extract_json_string(&json_object, "action"));
+                let suite_object  = try!(extract_json_object_named(&json_object, "check_suite"));
+                event.sha         = try!(extract_json_string(&suite_object, "head_sha"));
+            }
+            _                            => {
+                event.action            = try!(extract_json_string(&json_object, "action"));
+                let pull_request_object = try!(extract_json_object_named(&json_object, "pull_request"));
+                event.number            = try!(extract_json_u64(&pull_request_object, "number"));
+                event.id                = try!(extract_json_u64(&pull_request_object, "id"));
+
+                let author_object  = try!(extract_json_object_named(&pull_request_object, "user"));
+                event.issue_author = try!(extract_json_string(&author_object, "login"));
+                let head_object    = try!(extract_json_object_named(&pull_request_object, "head"));
+                event.sha          = try!(extract_json_string(&head_object, "sha"));
+            }
+        }
+
+        thread_trace!("Return Ok");
+        Ok(event)
+    }
 }
 
 
@@ -337,13 +452,29 @@ impl WebhookEvent {
 
 //Utils
 
+//Base url of the Github API, can be overridden with "github_api_url"
+//so that the bot can be pointed to a local server (i.e. in tests)
+pub fn github_api_url(config: &mut config::ConfigHandler) -> String {
+    thread_trace!("webhooks.rs: github_api_url(config)");
+    match config.get_string("config", "github_api_url") {
+        Ok(url) => {
+            if url == String::new() {
+                return String::from("https://api.github.com")
+            }
+            return String::from(url.trim_right_matches('/'))
+        },
+        Err(_)  => return String::from("https://api.github.com")
+    }
+}
+
 //TODO: add bad request check
-pub fn github_post_request(endpoint: String, body: String) -> Result<(), String>{
+//Ok: The parsed response body, Null if it is empty
+pub fn github_post_request(api_url: String, endpoint: String, body: String) -> Result<serde_json::Value, String>{
 
-    thread_trace!("webhooks.rs: github_post_request({}, {})", endpoint, body);
+    thread_trace!("webhooks.rs: github_post_request({}, {}, {})", api_url, endpoint, body);
 
     let     http_client   = Client::new();
-    let     api_call      = format!("https://api.github.com/{}", endpoint);
+    let     api_call      = format!("{}/{}", api_url, endpoint);
     let     body_len      = body.len().clone();
     let mut header        = Headers::new();
 
@@ -361,7 +492,7 @@ pub fn github_post_request(endpoint: String, body: String) -> Result<(), String>
 
     header.set_raw("User-Agent", vec![b"hunter-bot".to_vec()]);
     thread_trace!("  Post request");
-    let response = match http_client.post(api_call_url)
+    let mut response = match http_client.post(api_call_url)
         .headers(header)
         .body(Body::BufBody(&body.into_bytes()[..], body_len))
         .send() {
@@ -386,8 +517,31 @@ pub fn github_post_request(endpoint: String, body: String) -> Result<(), String>
     }
     thread_trace!("    Ok");
 
-    thread_trace!("Return Ok");
-    Ok(())
+    thread_trace!("  Status server error check");
+    if response.status.is_server_error() {
+        thread_trace!("Return Err");
+        return Err(format!("Github responded with a server error: {}", response.status))
+    }
+    thread_trace!("    Ok");
+
+    thread_trace!("  Read response body");
+    let mut response_body = String::new();
+    match response.read_to_string(&mut response_body) {
+        Ok(_)    => (),
+        Err(err) => return Err(format!("Failed to read the API response: {}", err))
+    }
+    if response_body.trim().is_empty() {
+        thread_trace!("Return Ok(Null)");
+        return Ok(serde_json::Value::Null)
+    }
+
+    match serde_json::from_str(&response_body[..]) {
+        Ok(value) => {
+            thread_trace!("Return Ok");
+            Ok(value)
+        }
+        Err(err)  => Err(format!("Failed to parse the API response: {}", err))
+    }
 }
 
 pub fn validate_webhook(tsconfig: &Arc<Mutex<config::ConfigHandler>>, header_string: &String, body_string: &String) -> Result<bool, IronResult<Response>> {
@@ -416,20 +570,36 @@ pub fn validate_webhook(tsconfig: &Arc<Mutex<config::ConfigHandler>>, header_str
 
     //Compute hmac
     thread_trace!("  Compute HMAC");
-    let hmac_array                = match hmac(Type::SHA1, github_webhook_secret.as_bytes(), body_string.as_bytes()) {
+    let signature_string_actual = match compute_webhook_signature(&github_webhook_secret, body_string) {
+        Ok(signature) => signature,
+        Err(err)      => {
+            thread_trace!("Return Err");
+            return Err(Ok(Response::with((status::InternalServerError, err))));
+        }
+    };
+
+    thread_trace!("  HMAC matches: {}", (header_string.clone() == signature_string_actual));
+    thread_trace!("Return Ok");
+    Ok(header_string.clone() == signature_string_actual)
+}
+
+//Computes the "X-Hub-Signature" value Github sends along with a webhook body
+pub fn compute_webhook_signature(secret: &str, body: &str) -> Result<String, String> {
+
+    thread_trace!("webhooks.rs: compute_webhook_signature(secret, body)");
+
+    let hmac_array = match hmac(Type::SHA1, secret.as_bytes(), body.as_bytes()) {
         Ok(hmac) => hmac,
         Err(_)   => {
             thread_trace!("Return Err");
-            return Err(Ok(Response::with((status::InternalServerError, "Failed to compute HMAC value."))));
+            return Err(String::from("Failed to compute HMAC value."))
         }
     };
     let hmac_strings: Vec<String> = hmac_array.iter().map(|byte| format!("{:02X}", byte)).collect();
     let hmac_string               = hmac_strings.join("").to_lowercase();
-    let signature_string_actual   = format!("sha1={}", hmac_string);
 
-    thread_trace!("  HMAC matches: {}", (header_string.clone() == signature_string_actual));
     thread_trace!("Return Ok");
-    Ok(header_string.clone() == signature_string_actual)
+    Ok(format!("sha1={}", hmac_string))
 }
 
 pub fn extract_header_string(header: &iron::Headers, field: &str) -> Result<String, String> {
@@ -457,6 +627,14 @@ pub fn extract_header_string(header: &iron::Headers, field: &str) -> Result<Stri
     }
 }
 
+//"owner/repo" the event comes from, the followed repository if the payload does not tell
+fn event_repo(config: &mut config::ConfigHandler, json_object: &BTreeMap<String, serde_json::Value>) -> String {
+    match json_object.get("repository").and_then(|repository| repository.find("full_name")).and_then(|full_name| full_name.as_str()) {
+        Some(full_name) => String::from(full_name),
+        None            => config.get_string_required("config", "github_follow_repo")
+    }
+}
+
 pub fn extract_json_object(value: &serde_json::Value) -> Result<BTreeMap<String, serde_json::Value>, String> {
     thread_trace!("webhook.rs: extract_json_object(value)");
     thread_trace!("  Value to object");
@@ -506,13 +684,20 @@ pub fn register(config: &mut config::ConfigHandler) {
 
     thread_trace!("webhooks.rs: register(config)");
 
+    //Nothing is created on Github in a dry run, hooks registered before still deliver
+    if config.is_dry_run() {
+        thread_info!("Dry run: skipping the webhook registration.");
+        return;
+    }
+
     //List of events to listent for.
-    let hooks = vec!["issue_comment","pull_request_review_comment"];
+    let hooks = vec!["issue_comment","pull_request_review_comment","pull_request","pull_request_review","status","check_suite"];
     thread_debug!("hooks: {:?}", hooks);
 
     thread_info!("Setting up webhooks...");
 
     //Get config vals
+    let     github_api_url     = github_api_url(config);
     let     github_follow_repo = config.get_string_required("config", "github_follow_repo");
     let     github_owner_token = config.get_string_required("config", "github_owner_token");
     let     listen_port        = config.get_string_required("config", "listen_port");
@@ -574,9 +759,22 @@ pub fn register(config: &mut config::ConfigHandler) {
 
     //Register webhooks
     let endpoint = format!("repos/{}/hooks?access_token={}", github_follow_repo, github_owner_token);
-    match github_post_request(endpoint, json_data_string) {
-        Ok(())   => (),
+    let hook = match github_post_request(github_api_url, endpoint, json_data_string) {
+        Ok(hook) => hook,
         Err(err) => {thread_crash!("Failed to register webhooks: {}", err);}
+    };
+
+    //Kept for the status command
+    match hook.find("id").and_then(|id| id.as_u64()) {
+        Some(id) => {
+            thread_debug!("Registered webhook {}", id);
+            config.set_string("state", "github_webhook_id", &id.to_string());
+            match config.save() {
+                Ok(())   => (),
+                Err(err) => {thread_error!("Failed to save the id of the webhook: {}", err);}
+            }
+        }
+        None     => {thread_warn!("The webhook was registered but Github did not give its id.");}
     }
 
     thread_info!("Success!");
@@ -632,13 +830,14 @@ pub fn listen(config: &mut config::ConfigHandler) {
     drop(config);
 
     //Process events
-    let command_handler = commands::CommandHandler::new(&tsconfig);
+    let outbound        = outbound::OutboundQueue::start(&tsconfig);
+    let command_handler = commands::CommandHandler::new(&tsconfig, &outbound);
     thread_debug!("command_handler: {:?}", command_handler);
     loop {
 
         //Dequeue
         let webhook_event = rx.recv().unwrap();
-        command_handler.parse_command(webhook_event);
+        command_handler.handle_event(webhook_event);
 
     }
 }
@@ -0,0 +1,231 @@
+//Copyright (c) 2016, Ruslan Baratov, Alex Frappier Lachapelle
+//All rights reserved.
+
+//Changes to the "whitelist" of the config made through the bot. The config
+//is saved after every change and the changes are logged in
+//"data_dir"/whitelist-changes.json, one JSON object per line.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+extern crate chrono;
+use self::chrono::*;
+
+extern crate hyper;
+use self::hyper::method::Method;
+
+extern crate serde_json;
+
+extern crate toml;
+
+use commands;
+use config;
+use github;
+use permissions::Role;
+use response;
+use response::Message;
+use store;
+use webhooks;
+
+include!("logger_macros.rs");
+
+const CHANGES_FILE_NAME: &'static str = "whitelist-changes.json";
+
+
+////////////////////////////////////////////////////////////
+//                    WhitelistChange                     //
+////////////////////////////////////////////////////////////
+
+/// WhitelistChange
+/// at:     Unix timestamp of the change
+/// by:     User who made the change
+/// action: "add" or "remove"
+/// user:   User who was added or removed
+#[derive(Clone, Debug, PartialEq)]
+pub struct WhitelistChange {
+    pub at:     i64,
+    pub by:     String,
+    pub action: String,
+    pub user:   String
+}
+
+impl WhitelistChange {
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut object: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+        object.insert(String::from("at"),     serde_json::Value::I64(self.at));
+        object.insert(String::from("by"),     serde_json::Value::String(self.by.clone()));
+        object.insert(String::from("action"), serde_json::Value::String(self.action.clone()));
+        object.insert(String::from("user"),   serde_json::Value::String(self.user.clone()));
+        serde_json::Value::Object(object)
+    }
+
+    pub fn from_json(value: &serde_json::Value) -> Result<WhitelistChange, String> {
+        let object = try!(value.as_object().ok_or(String::from("A whitelist change does not describe an object.")));
+        Ok(WhitelistChange {
+            at:     try!(value.find("at").and_then(|at| at.as_i64()).ok_or(String::from("The \"at\" field does not describe a number."))),
+            by:     try!(webhooks::extract_json_string(object, "by")),
+            action: try!(webhooks::extract_json_string(object, "action")),
+            user:   try!(webhooks::extract_json_string(object, "user"))
+        })
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                    WhitelistCommand                    //
+////////////////////////////////////////////////////////////
+
+///"whitelist add <user>", "whitelist remove <user>" and "whitelist list"
+pub struct WhitelistCommand;
+
+impl WhitelistCommand {
+    pub fn command() -> commands::Command {
+        commands::Command::new(false, Role::Owner, WhitelistCommand)
+            .summary("Adds users to the whitelist, removes them or lists them.")
+            .usage("whitelist <add|remove|list> [user]")
+            .argument("add|remove|list", true, "What to do with the whitelist.")
+            .argument("user", false, "Github user to add or remove.")
+            .example("whitelist add octocat")
+            .example("whitelist remove octocat")
+            .example("whitelist list")
+    }
+}
+
+impl commands::BotCommand for WhitelistCommand {
+    fn run(&self, context: &mut commands::CommandContext) -> Result<Message, String> {
+
+        thread_trace!("whitelist.rs: WhitelistCommand::run(&self, context)");
+
+        match (context.args.positional(0), context.args.positional(1)) {
+            (Some("add"), Some(user))    => {
+                let login = try!(add(&context.tsconfig, user, &context.event.user));
+                Ok(Message::text(&format!("{} was added to the whitelist.", response::inline_code(&login))))
+            }
+            (Some("remove"), Some(user)) => {
+                let login = try!(remove(&context.tsconfig, user, &context.event.user));
+                Ok(Message::text(&format!("{} was removed from the whitelist.", response::inline_code(&login))))
+            }
+            (Some("list"), None)         => {
+                let users = users(&context.tsconfig);
+                if users.is_empty() {
+                    return Ok(Message::text("The whitelist is empty."))
+                }
+
+                //Who added each user, if it was done through the bot
+                let mut added: BTreeMap<String, WhitelistChange> = BTreeMap::new();
+                for change in try!(changes(&context.tsconfig)) {
+                    if change.action == "add" {
+                        added.insert(change.user.to_lowercase(), change);
+                    }
+                }
+
+                let rows: Vec<Vec<String>> = users.iter().map(|user| {
+                    match added.get(&user.to_lowercase()) {
+                        Some(change) => vec![response::inline_code(user), change.by.clone(), UTC.timestamp(change.at, 0).format("%Y-%m-%d %H:%M:%S UTC").to_string()],
+                        None         => vec![response::inline_code(user), String::new(), String::new()]
+                    }
+                }).collect();
+                Ok(Message::text(&format!("{} whitelisted user(s):", users.len()))
+                    .table(vec!["User", "Added by", "Added at"], rows))
+            }
+            _                            => Err(format!("Usage: {}", response::inline_code("whitelist <add|remove|list> [user]")))
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////
+//                          Funcs                         //
+////////////////////////////////////////////////////////////
+
+//Users in the whitelist of the config, the owner is not listed
+pub fn users(tsconfig: &Arc<Mutex<config::ConfigHandler>>) -> Vec<String> {
+    let mut config = tsconfig.lock().unwrap();
+    config.get_array_required("config", "whitelist").iter()
+        .filter_map(|user| user.as_str().map(String::from))
+        .collect()
+}
+
+//Adds a Github user to the whitelist on behalf of `by`.
+//Ok: The login as spelled on Github, Err if the user does not exist or is already whitelisted
+pub fn add(tsconfig: &Arc<Mutex<config::ConfigHandler>>, user: &str, by: &str) -> Result<String, String> {
+
+    thread_trace!("whitelist.rs: add(tsconfig, \"{}\", \"{}\")", user, by);
+
+    let login = try!(github_login(tsconfig, user));
+    {
+        let mut config    = tsconfig.lock().unwrap();
+        let mut whitelist = config.get_array_required("config", "whitelist");
+        if whitelist.iter().any(|entry| entry.as_str().map(|entry| entry.to_lowercase()) == Some(login.to_lowercase())) {
+            return Err(format!("{} is already whitelisted.", response::inline_code(&login)))
+        }
+        whitelist.push(toml::Value::String(login.clone()));
+        config.set_array("config", "whitelist", &whitelist);
+        try!(config.save().map_err(|err| format!("Failed to save the config: {}", err)));
+    }
+
+    try!(record(tsconfig, by, "add", &login));
+    thread_info!("{} added {} to the whitelist", by, login);
+    Ok(login)
+}
+
+//Removes a user from the whitelist on behalf of `by`.
+//Ok: The login as it was spelled in the whitelist, Err if the user is not whitelisted
+pub fn remove(tsconfig: &Arc<Mutex<config::ConfigHandler>>, user: &str, by: &str) -> Result<String, String> {
+
+    thread_trace!("whitelist.rs: remove(tsconfig, \"{}\", \"{}\")", user, by);
+
+    let login: String;
+    {
+        let mut config    = tsconfig.lock().unwrap();
+        let mut whitelist = config.get_array_required("config", "whitelist");
+        let index         = match whitelist.iter().position(|entry| entry.as_str().map(|entry| entry.to_lowercase()) == Some(user.to_lowercase())) {
+            Some(index) => index,
+            None        => return Err(format!("{} is not whitelisted.", response::inline_code(user)))
+        };
+        login = String::from(whitelist.remove(index).as_str().unwrap_or(user));
+        config.set_array("config", "whitelist", &whitelist);
+        try!(config.save().map_err(|err| format!("Failed to save the config: {}", err)));
+    }
+
+    try!(record(tsconfig, by, "remove", &login));
+    thread_info!("{} removed {} from the whitelist", by, login);
+    Ok(login)
+}
+
+//Changes made through the bot, oldest first
+pub fn changes(tsconfig: &Arc<Mutex<config::ConfigHandler>>) -> Result<Vec<WhitelistChange>, String> {
+    let mut changes: Vec<WhitelistChange> = Vec::new();
+    for entry in try!(store::Store::new(tsconfig).entries(CHANGES_FILE_NAME)) {
+        changes.push(try!(WhitelistChange::from_json(&entry)));
+    }
+    Ok(changes)
+}
+
+fn record(tsconfig: &Arc<Mutex<config::ConfigHandler>>, by: &str, action: &str, user: &str) -> Result<(), String> {
+    let change = WhitelistChange {
+        at:     UTC::now().timestamp(),
+        by:     String::from(by),
+        action: String::from(action),
+        user:   String::from(user)
+    };
+    store::Store::new(tsconfig).append(CHANGES_FILE_NAME, &change.to_json())
+        .map_err(|err| format!("Failed to record the whitelist change: {}", err))
+}
+
+//Login of an existing Github user
+fn github_login(tsconfig: &Arc<Mutex<config::ConfigHandler>>, user: &str) -> Result<String, String> {
+
+    if user.is_empty() || !user.chars().all(|c| c.is_alphanumeric() || c == '-') {
+        return Err(format!("{} is not a valid Github user name.", response::inline_code(user)))
+    }
+
+    let client          = github::GithubClient::bot(tsconfig);
+    let (status, value) = try!(client.request(Method::Get, &format!("users/{}", user), None));
+    match status {
+        200 => Ok(String::from(value.find("login").and_then(|login| login.as_str()).unwrap_or(user))),
+        404 => Err(format!("There is no Github user named {}.", response::inline_code(user))),
+        _   => Err(format!("Failed to look up the Github user {}, status {}.", response::inline_code(user), status))
+    }
+}